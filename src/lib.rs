@@ -0,0 +1,1104 @@
+//! descrambling of images scrambled as described by a ptimg.
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("network: {0}")]
+    Network(reqwest::Error),
+
+    #[error("io: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("json: {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("coord: {0}")]
+    Coord(String),
+
+    #[error("decode: {0}")]
+    Decode(image::ImageError),
+
+    #[error("encode: {0}")]
+    Encode(image::ImageError),
+
+    #[error("validation: {0}")]
+    Validation(String),
+
+    #[error("timed out after {0:?}")]
+    Timeout(std::time::Duration),
+
+    #[error("{} resources failed: {}", .0.len(), join(.0))]
+    Resources(Vec<Error>),
+}
+
+fn join(errors: &[Error]) -> String {
+    errors
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+impl Error {
+    /// a short name of the variant, e.g. for reports.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Network(_) => "network",
+            Self::Io(_) => "io",
+            Self::Json(_) => "json",
+            Self::Coord(_) => "coord",
+            Self::Decode(_) => "decode",
+            Self::Encode(_) => "encode",
+            Self::Validation(_) => "validation",
+            Self::Timeout(_) => "timeout",
+            Self::Resources(_) => "resources",
+        }
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(mut e: reqwest::Error) -> Self {
+        // credentials given as userinfo mustn't end up in the logs
+        if let Some(url) = e.url_mut() {
+            let _ = url.set_username("");
+            let _ = url.set_password(None);
+        }
+
+        Self::Network(e)
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// decodes an image, refusing one of more than `max_pixels` pixels before
+/// allocating for it.
+pub fn decode(bytes: &[u8], max_pixels: u64) -> Result<image::DynamicImage> {
+    decode_with_profile(bytes, max_pixels).map(|(img, _)| img)
+}
+
+/// like `decode`, also giving the icc profile embedded in the image, if any.
+pub fn decode_with_profile(
+    bytes: &[u8],
+    max_pixels: u64,
+) -> Result<(image::DynamicImage, Option<Vec<u8>>)> {
+    use image::ImageDecoder;
+
+    let reader = || image::ImageReader::new(std::io::Cursor::new(bytes)).with_guessed_format();
+
+    let (width, height) = reader()?.into_dimensions().map_err(Error::Decode)?;
+    if width as u64 * height as u64 > max_pixels {
+        return Err(Error::Validation(format!(
+            "image of {width}x{height} exceeds {max_pixels} pixels"
+        )));
+    }
+
+    // in case decoding disagrees with the header, at up to 16 bytes per pixel
+    let mut limits = image::Limits::default();
+    limits.max_alloc = Some(max_pixels.saturating_mul(16));
+
+    let mut reader = reader()?;
+    reader.limits(limits);
+
+    let mut decoder = reader.into_decoder().map_err(Error::Decode)?;
+    let icc = decoder.icc_profile().map_err(Error::Decode)?;
+    let img = image::DynamicImage::from_decoder(decoder).map_err(Error::Decode)?;
+
+    Ok((img, icc))
+}
+
+/// an image format the `image` crate knows of.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Format {
+    /// its usual extension, e.g. `webp`.
+    pub name: &'static str,
+    pub mime: &'static str,
+    /// whether `decode` can read it, i.e. source images may be of it.
+    pub decode: bool,
+    /// whether the `image` crate can write it.
+    pub encode: bool,
+}
+
+/// the image formats the `image` crate knows of, with what it was compiled
+/// with support for. either depends on its features, e.g. avif is only
+/// decoded with `avif-native`.
+pub fn formats() -> Vec<Format> {
+    image::ImageFormat::all()
+        .map(|f| Format {
+            name: f.extensions_str().first().copied().unwrap_or("?"),
+            mime: f.to_mime_type(),
+            decode: f.reading_enabled(),
+            encode: f.writing_enabled(),
+        })
+        .collect()
+}
+
+/// descrambles `source` as `ptimg` (json, which may be gzip compressed)
+/// describes, into an image per view. every resource it refers to is taken to
+/// be `source`, as for pages having a single source image; `Ptimg::restore`
+/// takes one per resource. nothing but the arguments is read, and nothing
+/// written.
+///
+/// ```
+/// use image::{DynamicImage, Rgba, RgbaImage};
+///
+/// // two pixels, swapped
+/// let source = RgbaImage::from_fn(2, 1, |x, _| Rgba([x as u8, 0, 0, 255]));
+/// let ptimg = br#"{
+///     "ptimg-version": 1,
+///     "resources": { "i": { "src": "0001.jpg", "width": 2, "height": 1 } },
+///     "views": [{ "width": 2, "height": 1, "coords": ["i:0,0+1,1>1,0", "i:1,0+1,1>0,0"] }]
+/// }"#;
+///
+/// let views = bbbsc::descramble(ptimg, &DynamicImage::ImageRgba8(source))?;
+///
+/// assert_eq!(views[0].get_pixel(0, 0), &Rgba([1, 0, 0, 255]));
+/// assert_eq!(views[0].get_pixel(1, 0), &Rgba([0, 0, 0, 255]));
+/// # Ok::<(), bbbsc::Error>(())
+/// ```
+pub fn descramble(ptimg: &[u8], source: &image::DynamicImage) -> Result<Vec<image::RgbaImage>> {
+    Ptimg::from_slice(ptimg)?.restore(|_| source)
+}
+
+/// fields it doesn't know, here or in its resources and views, are ignored, so
+/// that newer variants of ptimg still parse.
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Ptimg {
+    pub ptimg_version: usize,
+    pub resources: std::collections::HashMap<String, Resource>,
+    pub views: Vec<View>,
+    /// of the chapter, which some sources include.
+    #[serde(default)]
+    pub title: Option<String>,
+}
+
+impl Ptimg {
+    /// parses ptimg json, which may be gzip compressed.
+    pub fn from_slice(bytes: &[u8]) -> Result<Self> {
+        if bytes.starts_with(&[0x1f, 0x8b]) {
+            use std::io::Read;
+
+            let mut json = Vec::new();
+            flate2::read::GzDecoder::new(bytes).read_to_end(&mut json)?;
+
+            return Ok(serde_json::from_slice(&json)?);
+        }
+
+        Ok(serde_json::from_slice(bytes)?)
+    }
+
+    pub fn restore<'a>(
+        &self,
+        map: impl Fn(&str) -> &'a image::DynamicImage,
+    ) -> Result<Vec<image::RgbaImage>> {
+        self.restore_with(map, &RestoreOptions::default())
+    }
+
+    /// coords are in terms of the size a resource declares. when the image
+    /// `map` gives for it has another size, tiles are cropped from the
+    /// correspondingly scaled rectangle and resized back.
+    pub fn restore_with<'a>(
+        &self,
+        map: impl Fn(&str) -> &'a image::DynamicImage,
+        opts: &RestoreOptions,
+    ) -> Result<Vec<image::RgbaImage>> {
+        self.views
+            .iter()
+            .map(|v| {
+                let mut dst = image::RgbaImage::new(v.width, v.height);
+                self.restore_view(v, &map, opts, &mut dst)?;
+                Ok(dst)
+            })
+            .collect::<Result<Vec<_>>>()
+    }
+
+    /// like `restore_with`, but lends each view to `f` in turn instead of
+    /// returning them all. views are restored into the same buffer as long as
+    /// their sizes match, which saves allocating one for each.
+    pub fn restore_each_with<'a>(
+        &self,
+        map: impl Fn(&str) -> &'a image::DynamicImage,
+        opts: &RestoreOptions,
+        mut f: impl FnMut(&image::RgbaImage) -> Result<()>,
+    ) -> Result<()> {
+        let mut dst = image::RgbaImage::new(0, 0);
+
+        for v in &self.views {
+            if dst.dimensions() == (v.width, v.height) {
+                // what coords don't cover has to stay transparent
+                dst.fill(0);
+            } else {
+                dst = image::RgbaImage::new(v.width, v.height);
+            }
+
+            self.restore_view(v, &map, opts, &mut dst)?;
+            f(&dst)?;
+        }
+
+        Ok(())
+    }
+
+    /// rewrites coords given in fractions (see `parse_normalized_coord`) into
+    /// pixels, of the size a resource declares, or else of the image `map`
+    /// gives for it.
+    pub fn denormalize<'a>(&mut self, map: impl Fn(&str) -> &'a image::DynamicImage) -> Result<()> {
+        for v in &mut self.views {
+            let view = (v.width, v.height);
+
+            for coord in &mut v.coords {
+                let (key, n) = parse_normalized_coord(coord)?;
+                let (size, _) = extent(self.resources.get(key), map(key));
+                let rep = n.to_pixels(size, view);
+
+                *coord = format!(
+                    "{key}:{},{}+{},{}>{},{}",
+                    rep.src.x, rep.src.y, rep.size.x, rep.size.y, rep.dst.x, rep.dst.y,
+                );
+                if rep.scale != 1.0 {
+                    *coord += &format!("@{}x", rep.scale);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// everything wrong with this that can be told without its resources: an
+    /// unknown version, coords that don't parse, refer to no resource, or
+    /// reach beyond its declared size or out of their view.
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+
+        match self.ptimg_version {
+            1 => {}
+            2 => problems.push("ptimg-version 2 isn't supported yet".to_owned()),
+            n => problems.push(format!("unknown ptimg-version {n}")),
+        }
+        if self.views.is_empty() {
+            problems.push("no views".to_owned());
+        }
+
+        for (i, v) in self.views.iter().enumerate() {
+            if v.z_index.len() > v.coords.len() {
+                problems.push(format!(
+                    "views[{i}]: {} z-indices for {} coords",
+                    v.z_index.len(),
+                    v.coords.len()
+                ));
+            }
+
+            for coord in &v.coords {
+                let mut problem = |p: String| problems.push(format!("views[{i}]: {p}"));
+
+                let (key, rep) = match parse_coord(coord) {
+                    Ok(c) => c,
+                    Err(e) => {
+                        problem(e.to_string());
+                        continue;
+                    }
+                };
+
+                match self.resources.get(key) {
+                    None => problem(format!("`{coord}` refers to {key}, which isn't a resource")),
+                    Some(r) if r.width > 0 && r.height > 0 => {
+                        if let Err(e) = fits(coord, key, &rep, (r.width as u64, r.height as u64)) {
+                            problem(e.to_string());
+                        }
+                    }
+                    Some(_) => {}
+                }
+
+                let placed = rep.placed();
+                let right = rep.dst.x + placed.x as i64;
+                let bottom = rep.dst.y + placed.y as i64;
+                if rep.dst.x < 0
+                    || rep.dst.y < 0
+                    || right > v.width as i64
+                    || bottom > v.height as i64
+                {
+                    problem(format!(
+                        "`{coord}` reaches out of the view of {}x{}",
+                        v.width, v.height
+                    ));
+                }
+            }
+        }
+
+        problems
+    }
+
+    /// restores `v` into `dst` as `ptimg-version` tells. a version is added as
+    /// a `restore_view_v*` of its own with an arm here, and one in `problems`.
+    fn restore_view<'a>(
+        &self,
+        v: &View,
+        map: &impl Fn(&str) -> &'a image::DynamicImage,
+        opts: &RestoreOptions,
+        dst: &mut image::RgbaImage,
+    ) -> Result<()> {
+        match self.ptimg_version {
+            1 => self.restore_view_v1(v, map, opts, dst),
+            2 => self.restore_view_v2(v, map, opts, dst),
+            n => Err(Error::Validation(format!("unknown ptimg-version {n}"))),
+        }
+    }
+
+    fn restore_view_v1<'a>(
+        &self,
+        v: &View,
+        map: &impl Fn(&str) -> &'a image::DynamicImage,
+        opts: &RestoreOptions,
+        dst: &mut image::RgbaImage,
+    ) -> Result<()> {
+        for coord in v.drawing_order() {
+            let (key, rep) = parse_coord(&coord)?;
+            let src = map(key);
+
+            let (size, scale) = extent(self.resources.get(key), src);
+
+            fits(coord, key, &rep, size)?;
+            rep.draw(src, dst, scale, opts);
+        }
+
+        Ok(())
+    }
+
+    /// a placeholder for a version 2, which may give coords other meanings,
+    /// until a source serving it is known.
+    fn restore_view_v2<'a>(
+        &self,
+        _: &View,
+        _: &impl Fn(&str) -> &'a image::DynamicImage,
+        _: &RestoreOptions,
+        _: &mut image::RgbaImage,
+    ) -> Result<()> {
+        Err(Error::Validation(
+            "ptimg-version 2 isn't supported yet".to_owned(),
+        ))
+    }
+}
+
+/// that the source of `rep`, parsed from `coord`, lies within `key` of `size`.
+fn fits(coord: &str, key: &str, rep: &Replacer, size: (u64, u64)) -> Result<()> {
+    let right = rep.src.x as u64 + rep.size.x as u64;
+    let bottom = rep.src.y as u64 + rep.size.y as u64;
+
+    if right > size.0 || bottom > size.1 {
+        return Err(Error::Validation(format!(
+            "`{coord}` reaches {right}x{bottom}, beyond {key} of {}x{}",
+            size.0, size.1,
+        )));
+    }
+
+    Ok(())
+}
+
+/// the size coords address for a resource, which is the declared one, or the
+/// fetched one of `src` if none is declared, along with how `src` scales it.
+fn extent(declared: Option<&Resource>, src: &image::DynamicImage) -> ((u64, u64), (f64, f64)) {
+    match declared {
+        Some(r) if r.width > 0 && r.height > 0 => (
+            (r.width as u64, r.height as u64),
+            (
+                src.width() as f64 / r.width as f64,
+                src.height() as f64 / r.height as f64,
+            ),
+        ),
+        _ => ((src.width() as u64, src.height() as u64), (1.0, 1.0)),
+    }
+}
+
+pub struct RestoreOptions {
+    /// resamples tiles of resources whose size differs from the declared one.
+    pub filter: image::imageops::FilterType,
+    /// continues what of a tile reaches past an edge of its view at the
+    /// opposite one, rather than clipping it.
+    pub wrap: bool,
+}
+
+impl Default for RestoreOptions {
+    fn default() -> Self {
+        Self {
+            filter: image::imageops::FilterType::Lanczos3,
+            wrap: false,
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Resource {
+    /// empty when not given, as for one of inline `data`.
+    #[serde(default)]
+    pub src: String,
+    /// 0 when not declared, where coords address the fetched size.
+    #[serde(default)]
+    pub width: usize,
+    #[serde(default)]
+    pub height: usize,
+    /// the image itself, in base64 (standard alphabet, padded), for ptimg
+    /// carrying its images. it takes precedence over `src`.
+    #[serde(default)]
+    pub data: Option<String>,
+}
+
+impl Resource {
+    /// the bytes of the image in `data`, if any.
+    pub fn inline(&self) -> Option<Result<Vec<u8>>> {
+        use base64::Engine;
+
+        let data = self.data.as_ref()?;
+        let bytes = base64::engine::general_purpose::STANDARD
+            .decode(data)
+            .map_err(|e| Error::Validation(format!("inline data has invalid base64: {e}")));
+
+        Some(bytes)
+    }
+
+    /// whether `img` is of the size declared, if any.
+    pub fn matches(&self, img: &image::DynamicImage) -> bool {
+        let declared = (self.width, self.height);
+        declared.0 == 0
+            || declared.1 == 0
+            || declared == (img.width() as usize, img.height() as usize)
+    }
+}
+
+#[derive(Clone, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct View {
+    pub width: u32,
+    pub height: u32,
+    pub coords: Vec<String>,
+    /// what the view is, e.g. `thumbnail`, which some sources tell.
+    #[serde(default)]
+    pub label: Option<String>,
+    /// of each coord, for formats whose tiles overlap: higher ones are drawn
+    /// over lower ones. missing ones are 0.
+    #[serde(default)]
+    pub z_index: Vec<i64>,
+}
+
+impl View {
+    /// `coords` in the order they're drawn in: by `z_index`, and as they are
+    /// among equal ones.
+    pub fn drawing_order(&self) -> Vec<&str> {
+        let z = |i: usize| self.z_index.get(i).copied().unwrap_or(0);
+
+        let mut order = (0..self.coords.len()).collect::<Vec<_>>();
+        order.sort_by_key(|&i| z(i));
+
+        order.into_iter().map(|i| self.coords[i].as_str()).collect()
+    }
+}
+
+/// names for `views` but the first: their labels, of which anything but
+/// alphanumerics, `-` and `_` is replaced by `_`, or else their position from
+/// 1 on. a name taken by an earlier view gets `-` and the position appended,
+/// and then further numbers until it's free, so no two are the same.
+pub fn view_names(views: &[View]) -> Vec<String> {
+    let mut taken = std::collections::HashSet::new();
+
+    (2..)
+        .zip(views.get(1..).unwrap_or_default())
+        .map(|(n, v)| {
+            let label = v.label.as_deref().map(str::trim).unwrap_or_default();
+
+            let base = match label {
+                "" => n.to_string(),
+                l => l
+                    .chars()
+                    .map(|c| match c {
+                        'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' => c,
+                        _ => '_',
+                    })
+                    .collect(),
+            };
+
+            let mut name = base.clone();
+            for k in n.. {
+                if taken.insert(name.clone()) {
+                    break;
+                }
+                name = format!("{base}-{k}");
+            }
+            name
+        })
+        .collect()
+}
+
+#[derive(serde::Serialize)]
+pub struct Vec2<T> {
+    pub x: T,
+    pub y: T,
+}
+
+impl<T> Vec2<T> {
+    pub fn new(x: T, y: T) -> Self {
+        Self { x, y }
+    }
+}
+
+#[derive(serde::Serialize)]
+pub struct Replacer {
+    pub size: Vec2<u32>,
+    pub src: Vec2<u32>,
+    pub dst: Vec2<i64>,
+    /// how many times larger the tile is in the source than where it's placed,
+    /// as annotated like `@2x`. 1 otherwise.
+    #[serde(skip_serializing_if = "is_unscaled")]
+    pub scale: f64,
+}
+
+/// a `Replacer` in fractions, as `parse_normalized_coord` gives.
+#[derive(serde::Serialize)]
+pub struct Normalized {
+    pub size: Vec2<f64>,
+    pub src: Vec2<f64>,
+    pub dst: Vec2<f64>,
+    #[serde(skip_serializing_if = "is_unscaled")]
+    pub scale: f64,
+}
+
+fn is_unscaled(scale: &f64) -> bool {
+    *scale == 1.0
+}
+
+impl Normalized {
+    /// in pixels of a resource of `source` and a view of `view`. edges are
+    /// rounded rather than sizes, so that adjacent tiles stay adjacent.
+    pub fn to_pixels(&self, source: (u64, u64), view: (u32, u32)) -> Replacer {
+        let px = |f: f64, of: u64| (f * of as f64).round();
+
+        let (x0, y0) = (px(self.src.x, source.0), px(self.src.y, source.1));
+        let x1 = px(self.src.x + self.size.x, source.0);
+        let y1 = px(self.src.y + self.size.y, source.1);
+
+        Replacer::new(
+            Vec2::new((x1 - x0) as u32, (y1 - y0) as u32),
+            Vec2::new(x0 as u32, y0 as u32),
+            Vec2::new(
+                px(self.dst.x, view.0 as u64) as i64,
+                px(self.dst.y, view.1 as u64) as i64,
+            ),
+        )
+        .with_scale(self.scale)
+    }
+}
+
+impl Replacer {
+    pub fn new(size: Vec2<u32>, src: Vec2<u32>, dst: Vec2<i64>) -> Self {
+        Self {
+            size,
+            src,
+            dst,
+            scale: 1.0,
+        }
+    }
+
+    pub fn with_scale(self, scale: f64) -> Self {
+        Self { scale, ..self }
+    }
+
+    /// the size the tile takes where it's placed, which is its size in the
+    /// source divided by `scale`, rounded, but never nothing of something.
+    pub fn placed(&self) -> Vec2<u32> {
+        let px = |s: u32| match s {
+            0 => 0,
+            s => ((s as f64 / self.scale).round() as u32).max(1),
+        };
+
+        Vec2::new(px(self.size.x), px(self.size.y))
+    }
+}
+
+impl Replacer {
+    pub fn apply<T, U>(&self, src: &T, dst: &mut U)
+    where
+        T: image::GenericImageView<Pixel = U::Pixel>,
+        U: image::GenericImage,
+        U::Pixel: 'static,
+    {
+        self.draw(src, dst, (1.0, 1.0), &RestoreOptions::default());
+    }
+
+    /// like `apply`, for a `src` scaled by `scale` relative to the coords,
+    /// resampling by `filter` as that or the tile's own `scale` needs.
+    pub fn apply_scaled<T, U>(
+        &self,
+        src: &T,
+        dst: &mut U,
+        scale: (f64, f64),
+        filter: image::imageops::FilterType,
+    ) where
+        T: image::GenericImageView<Pixel = U::Pixel>,
+        U: image::GenericImage,
+        U::Pixel: 'static,
+    {
+        let opts = RestoreOptions {
+            filter,
+            ..Default::default()
+        };
+        self.draw(src, dst, scale, &opts);
+    }
+
+    fn draw<T, U>(&self, src: &T, dst: &mut U, scale: (f64, f64), opts: &RestoreOptions)
+    where
+        T: image::GenericImageView<Pixel = U::Pixel>,
+        U: image::GenericImage,
+        U::Pixel: 'static,
+    {
+        if scale == (1.0, 1.0) && self.scale == 1.0 {
+            return place(dst, &*self.crop(src), (self.dst.x, self.dst.y), opts.wrap);
+        }
+
+        let x = (self.src.x as f64 * scale.0).round() as u32;
+        let y = (self.src.y as f64 * scale.1).round() as u32;
+        let w = (self.size.x as f64 * scale.0).round().max(1.0) as u32;
+        let h = (self.size.y as f64 * scale.1).round().max(1.0) as u32;
+
+        let placed = self.placed();
+        let part = image::imageops::crop_imm(src, x, y, w, h);
+        let part = image::imageops::resize(&*part, placed.x, placed.y, opts.filter);
+
+        place(dst, &part, (self.dst.x, self.dst.y), opts.wrap);
+    }
+
+    /// the tile of `src` this places.
+    pub fn crop<'a, T: image::GenericImageView>(&self, src: &'a T) -> image::SubImage<&'a T> {
+        image::imageops::crop_imm(src, self.src.x, self.src.y, self.size.x, self.size.y)
+    }
+}
+
+/// draws `part` into `dst` at `at`, clipping what's past its edges, or with
+/// `wrap`, drawing that again from the opposite edges on.
+fn place<T, U>(dst: &mut U, part: &T, at: (i64, i64), wrap: bool)
+where
+    T: image::GenericImageView<Pixel = U::Pixel>,
+    U: image::GenericImage,
+{
+    let (width, height) = (dst.width() as i64, dst.height() as i64);
+    if !wrap || width == 0 || height == 0 {
+        return image::imageops::replace(dst, part, at.0, at.1);
+    }
+
+    // every copy shifted by whole views that still overlaps one
+    let (x, y) = (at.0.rem_euclid(width), at.1.rem_euclid(height));
+    for i in 0..=part.width() as i64 / width + 1 {
+        for j in 0..=part.height() as i64 / height + 1 {
+            image::imageops::replace(dst, part, x - i * width, y - j * height);
+        }
+    }
+}
+
+/// parses `key:x,y+w,h>x,y`, or `key:x,y~x,y>x,y` giving the opposite corner of
+/// the source rather than its size, allowing whitespace around the separators.
+/// either may be followed by a scale like `@2x`, for a source of twice the
+/// resolution, whose tile is placed at half its size.
+pub fn parse_coord(s: &impl AsRef<str>) -> Result<(&str, Replacer)> {
+    let (key, (src, size, dst, scale)) = coord(s.as_ref())?;
+    Ok((key, Replacer::new(size, src, dst).with_scale(scale)))
+}
+
+/// parses `key:x,y+w,h>x,y` in fractions, e.g. `i:0,0.5+0.5,0.5>0.5,0`, of the
+/// resource's size for the source rectangle and of the view's for the
+/// destination.
+pub fn parse_normalized_coord(s: &impl AsRef<str>) -> Result<(&str, Normalized)> {
+    let (key, (src, size, dst, scale)) = coord::<f64, f64>(s.as_ref())?;
+
+    if src.x < 0.0 || src.y < 0.0 || size.x < 0.0 || size.y < 0.0 {
+        return Err(Error::Coord(format!(
+            "`{}` has a negative source",
+            s.as_ref()
+        )));
+    }
+
+    Ok((
+        key,
+        Normalized {
+            size,
+            src,
+            dst,
+            scale,
+        },
+    ))
+}
+
+/// src, size, dst and scale of a coord.
+type Parts<T, U> = (Vec2<T>, Vec2<T>, Vec2<U>, f64);
+
+fn coord<T, U>(s: &str) -> Result<(&str, Parts<T, U>)>
+where
+    T: std::str::FromStr + PartialOrd + std::ops::Sub<Output = T> + Copy,
+    U: std::str::FromStr,
+{
+    use nom::branch::alt;
+    use nom::bytes::complete::tag;
+    use nom::character::complete::{alpha1, digit1, multispace0};
+    use nom::combinator::{all_consuming, map, map_res, opt, recognize};
+    use nom::sequence::{delimited, pair, preceded, separated_pair, terminated, tuple};
+    use nom::IResult;
+    use std::str::FromStr;
+
+    // signed and fractional for any `T`, `parse` rejects what an integer or
+    // unsigned one can't be
+    fn num<T: FromStr>(s: &str) -> IResult<&str, T> {
+        let frac = opt(pair(tag("."), digit1));
+        map_res(
+            recognize(tuple((opt(tag("-")), digit1, frac))),
+            |s: &str| s.parse::<T>(),
+        )(s)
+    }
+
+    fn sep<'a>(t: &'static str) -> impl FnMut(&'a str) -> IResult<&'a str, &'a str> {
+        delimited(multispace0, tag(t), multispace0)
+    }
+
+    fn vec<T: FromStr>(s: &str) -> IResult<&str, Vec2<T>> {
+        map(separated_pair(num, sep(","), num), |(l, r)| Vec2::new(l, r))(s)
+    }
+
+    // the opposite corner, exclusive, rather than the size
+    let corners = map_res(separated_pair(vec::<T>, sep("~"), vec::<T>), |(a, b)| {
+        if b.x < a.x || b.y < a.y {
+            return Err("the second corner is above or left of the first");
+        }
+
+        let size = Vec2::new(b.x - a.x, b.y - a.y);
+        Ok((a, size))
+    });
+
+    let scale = map_res(terminated(num::<f64>, tag("x")), |f| match f > 0.0 {
+        true => Ok(f),
+        false => Err("the scale isn't positive"),
+    });
+
+    let src = alt((separated_pair(vec, sep("+"), vec), corners));
+    let bdy = pair(
+        separated_pair(src, sep(">"), vec),
+        opt(preceded(sep("@"), scale)),
+    );
+    let whl = separated_pair(alpha1, sep(":"), bdy);
+
+    match all_consuming(delimited(multispace0, whl, multispace0))(s) {
+        Ok(("", (key, (((src, size), dst), scale)))) => {
+            Ok((key, (src, size, dst, scale.unwrap_or(1.0))))
+        }
+
+        Err(e) => Err(Error::Coord(e.to_string())),
+        _ => unreachable!(),
+    }
+}
+
+/// substitutes the next `{}` of `template`, but leaves `data:` urls untouched
+/// since their payload may legitimately contain `{}`. in the query, `value` is
+/// percent-encoded, so that e.g. `&` in it doesn't start another parameter.
+pub fn substitute(template: &str, value: &str) -> String {
+    use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
+
+    const QUERY: &AsciiSet = &CONTROLS
+        .add(b' ')
+        .add(b'"')
+        .add(b'#')
+        .add(b'%')
+        .add(b'&')
+        .add(b'+')
+        .add(b';')
+        .add(b'<')
+        .add(b'=')
+        .add(b'>');
+
+    if template.starts_with("data:") {
+        return template.to_owned();
+    }
+
+    let Some(at) = template.find("{}") else {
+        return template.to_owned();
+    };
+
+    let in_query = template[..at].contains('?');
+
+    let mut s = template[..at].to_owned();
+    if in_query {
+        s.extend(utf8_percent_encode(value, QUERY));
+    } else {
+        s.push_str(value);
+    }
+    s.push_str(&template[at + 2..]);
+
+    s
+}
+
+/// like `tokio::fs::create_dir_all`, for directories others may be creating at
+/// the same time, e.g. concurrent chapters sharing their parents. it's done
+/// once `path` is a directory, whoever created it; what fails as if another
+/// was midway creating or removing a parent is retried a few times.
+pub async fn create_dir_all(path: impl AsRef<std::path::Path>) -> std::io::Result<()> {
+    use std::io::ErrorKind::{AlreadyExists, Interrupted, NotFound};
+
+    let path = path.as_ref();
+
+    for n in 0.. {
+        let e = match tokio::fs::create_dir_all(path).await {
+            Ok(()) => return Ok(()),
+            Err(e) => e,
+        };
+
+        if tokio::fs::metadata(path).await.is_ok_and(|m| m.is_dir()) {
+            return Ok(());
+        }
+
+        if n == 3 || !matches!(e.kind(), AlreadyExists | NotFound | Interrupted) {
+            return Err(e);
+        }
+
+        tokio::time::sleep(std::time::Duration::from_millis(10 << n)).await;
+    }
+
+    unreachable!()
+}
+
+/// writes `bytes` to a sibling `.part` file first and moves it to `path` with
+/// `publish_new`, so that an interrupted write never leaves a truncated `path`
+/// behind. like `create_new`, fails when `path` already exists.
+///
+/// it's done on a blocking thread, to its end even when the future is dropped
+/// meanwhile (e.g. by a timeout), leaving either `path` written in full or
+/// nothing at all.
+pub async fn write_new_atomically(path: &str, bytes: &[u8]) -> Result<()> {
+    let (path, bytes) = (path.to_owned(), bytes.to_owned());
+
+    unabortable(move || {
+        let part = format!("{path}.part");
+
+        if let Err(e) = std::fs::write(&part, bytes) {
+            let _ = std::fs::remove_file(&part);
+            return Err(e)?;
+        }
+
+        publish_new(&part, &path)
+    })
+    .await
+}
+
+/// like `write_new_atomically`, with the content of `original`, hardlinked, or
+/// copied where that isn't supported.
+pub async fn link_new_atomically(original: &str, path: &str) -> Result<()> {
+    let (original, path) = (original.to_owned(), path.to_owned());
+
+    unabortable(move || {
+        let part = format!("{path}.part");
+        let _ = std::fs::remove_file(&part);
+
+        if std::fs::hard_link(&original, &part).is_err() {
+            if let Err(e) = std::fs::copy(&original, &part) {
+                let _ = std::fs::remove_file(&part);
+                return Err(e)?;
+            }
+        }
+
+        publish_new(&part, &path)
+    })
+    .await
+}
+
+/// runs `f` on a blocking thread, which goes on to its end whether or not what
+/// it gives back is still awaited.
+async fn unabortable<T, F>(f: F) -> Result<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T> + Send + 'static,
+{
+    match tokio::task::spawn_blocking(f).await {
+        Ok(res) => res,
+        Err(e) => std::panic::resume_unwind(e.into_panic()),
+    }
+}
+
+/// moves `part` to `path` at once, failing when `path` exists, even when it was
+/// created meanwhile by another write: by hardlinking it there, which doesn't
+/// replace anything, or where that isn't supported, by claiming `path` with
+/// `create_new` before renaming over it. `part` is gone either way, and so is a
+/// claimed `path` the rename failed to replace.
+fn publish_new(part: &str, path: &str) -> Result<()> {
+    use std::io::ErrorKind::AlreadyExists;
+
+    let exists = |e: std::io::Error| match e.kind() {
+        AlreadyExists => std::io::Error::new(AlreadyExists, format!("{path} already exists")),
+        _ => e,
+    };
+
+    let res = match std::fs::hard_link(part, path) {
+        Ok(()) => std::fs::remove_file(part),
+        Err(e) if e.kind() == AlreadyExists => Err(exists(e)),
+        Err(_) => match std::fs::File::options()
+            .write(true)
+            .create_new(true)
+            .open(path)
+        {
+            Ok(_) => std::fs::rename(part, path).inspect_err(|_| {
+                let _ = std::fs::remove_file(path);
+            }),
+            Err(e) => Err(exists(e)),
+        },
+    };
+
+    if res.is_err() {
+        let _ = std::fs::remove_file(part);
+    }
+
+    Ok(res?)
+}
+
+/// storage for fetched resources, by key (e.g. a path or url). a backend may be
+/// shared by concurrent fetches, even of the same key, so `put` must replace an
+/// entry at once: `get` never sees a partially written one, and the last `put`
+/// wins. a missing entry is `Ok(None)`, not an error.
+pub trait CacheBackend: Sync {
+    fn get(&self, key: &str) -> impl std::future::Future<Output = Result<Option<Vec<u8>>>> + Send;
+
+    fn put(&self, key: &str, bytes: &[u8]) -> impl std::future::Future<Output = Result<()>> + Send;
+
+    /// removes the entry of `key`, telling whether there was one.
+    fn remove(&self, key: &str) -> impl std::future::Future<Output = Result<bool>> + Send;
+}
+
+/// caches in files, the key being the path. entries are written to a `.part`
+/// sibling first and renamed into place.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FsCache;
+
+impl CacheBackend for FsCache {
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        match tokio::fs::read(key).await {
+            Ok(b) => Ok(Some(b)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e)?,
+        }
+    }
+
+    async fn put(&self, key: &str, bytes: &[u8]) -> Result<()> {
+        if let Some(dir) = std::path::Path::new(key).parent() {
+            create_dir_all(dir).await?;
+        }
+
+        let part = format!("{key}.part");
+        tokio::fs::write(&part, bytes).await?;
+        tokio::fs::rename(&part, key).await?;
+
+        Ok(())
+    }
+
+    async fn remove(&self, key: &str) -> Result<bool> {
+        match tokio::fs::remove_file(key).await {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e)?,
+        }
+    }
+}
+
+/// items fetched ahead of being taken, by `prefetch`. dropping it aborts
+/// fetching.
+pub struct Prefetch<T> {
+    items: tokio::sync::mpsc::Receiver<T>,
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl<T> Prefetch<T> {
+    /// the next item, once it's fetched, or `None` once fetching stopped.
+    pub async fn next(&mut self) -> Option<T> {
+        self.items.recv().await
+    }
+}
+
+impl<T> Drop for Prefetch<T> {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// fetches `items` one after another by `fetch`, on a task of its own, while
+/// they're taken from what it gives back. at most `buffer` of them wait to be
+/// taken, with one more being fetched, so that taking them slower than they're
+/// fetched slows fetching down rather than piling them up. it stops after the
+/// first one `fetch` breaks with, which is still given.
+pub fn prefetch<I, F, Fut, T>(items: I, buffer: std::num::NonZeroUsize, mut fetch: F) -> Prefetch<T>
+where
+    I: IntoIterator,
+    I::IntoIter: Send + 'static,
+    I::Item: Send,
+    F: FnMut(I::Item) -> Fut + Send + 'static,
+    Fut: std::future::Future<Output = std::ops::ControlFlow<T, T>> + Send + 'static,
+    T: Send + 'static,
+{
+    use std::ops::ControlFlow;
+
+    let (tx, items_rx) = tokio::sync::mpsc::channel(buffer.get());
+    let items = items.into_iter();
+
+    let task = tokio::spawn(async move {
+        for item in items {
+            let (item, last) = match fetch(item).await {
+                ControlFlow::Continue(t) => (t, false),
+                ControlFlow::Break(t) => (t, true),
+            };
+
+            if tx.send(item).await.is_err() || last {
+                break;
+            }
+        }
+    });
+
+    Prefetch {
+        items: items_rx,
+        task,
+    }
+}
+
+/// expands `${VAR}` by what `lookup` gives for `VAR`, or `${VAR:-default}` by
+/// `default` when that's nothing or empty. `$$` is a literal `$`, as is a `$`
+/// before anything else. an undefined variable without a default is an error.
+pub fn expand_env(s: &str, lookup: impl Fn(&str) -> Option<String>) -> Result<String> {
+    let mut out = String::new();
+    let mut rest = s;
+
+    while let Some(at) = rest.find('$') {
+        out.push_str(&rest[..at]);
+        rest = &rest[at + 1..];
+
+        if let Some(r) = rest.strip_prefix('$') {
+            out.push('$');
+            rest = r;
+            continue;
+        }
+
+        let Some(r) = rest.strip_prefix('{') else {
+            out.push('$');
+            continue;
+        };
+        let Some(end) = r.find('}') else {
+            return Err(Error::Validation(format!("unclosed `${{` in `{s}`")));
+        };
+        let (expr, r) = (&r[..end], &r[end + 1..]);
+
+        let (name, default) = match expr.split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (expr, None),
+        };
+
+        match (lookup(name), default) {
+            (Some(v), Some(d)) if v.is_empty() => out.push_str(d),
+            (Some(v), _) => out.push_str(&v),
+            (None, Some(d)) => out.push_str(d),
+            (None, None) => {
+                return Err(Error::Validation(format!("`{name}` isn't defined")));
+            }
+        }
+
+        rest = r;
+    }
+
+    out.push_str(rest);
+
+    Ok(out)
+}
@@ -1,259 +1,6092 @@
 use std::ops::ControlFlow;
+use std::sync::Arc;
 
-type Error = Box<dyn std::error::Error>;
-type Result<T> = std::result::Result<T, Error>;
+use bbbsc::{substitute, Error, Ptimg, Result};
+
+struct Options {
+    client: reqwest::Client,
+    host_limit: Option<HostLimit>,
+    ramp_up: Option<RampUp>,
+    delay: Option<Delay>,
+    page_timeout: Option<std::time::Duration>,
+    encoding: Encoding,
+    debug_pages: Pages,
+    dump_tiles: Option<String>,
+    debug_visualize: Option<String>,
+    export_coords: Option<String>,
+    verbose: bool,
+    sanitize: Sanitize,
+    html_extract: HtmlExtract,
+    no_cache: bool,
+    /// whether cache misses are skipped rather than fetched
+    offline: bool,
+    /// whether a miss was warned about with `--offline`, which is only once.
+    missed: std::sync::atomic::AtomicBool,
+    url_cache: Option<UrlCache>,
+    restore: bbbsc::RestoreOptions,
+    normalized_coords: bool,
+    lenient: bool,
+    /// whether failed pages are tried once more at the end of their chapter
+    retry_failed_pages: bool,
+    /// whether pages written are recorded for `--manifest`, with their timings
+    /// for `--manifest-timings`.
+    manifest: bool,
+    manifest_timings: bool,
+    budget: Budget,
+    embed_metadata: bool,
+    detect_pad: bool,
+    chapter_gap_tolerance: usize,
+    chapter_dir: String,
+    flat: bool,
+    clean_output: bool,
+    all_views: bool,
+    kinds: Vec<Kind>,
+    refetch_on_size_mismatch: bool,
+    verify_pairing: bool,
+    check_dimensions: Option<DimensionCheck>,
+    /// how far a page may be off the size most of its chapter is of, as a
+    /// fraction of it.
+    dimension_tolerance: f64,
+    contact_sheet: Option<ContactSheet>,
+    cbz: bool,
+    archive_chunk_size: Option<usize>,
+    tar: Option<Tar>,
+    icc: Option<ColorProfile>,
+    force: bool,
+    /// what's written depends on, for `--state`.
+    state: Option<String>,
+    head_first: bool,
+    resource_concurrency: usize,
+    simulate_errors: Option<SimulatedErrors>,
+    dedupe_output: Option<Dedupe>,
+    max_image_pixels: u64,
+    checksums: bool,
+    channels: Channels,
+    drop_opaque_alpha: bool,
+    coords_override: std::collections::HashMap<(usize, usize), Override>,
+    accept: Option<reqwest::header::HeaderValue>,
+    trace_http: bool,
+    stitch_spreads: Option<Spreads>,
+    right_to_left: bool,
+    print_size: Option<PrintSize>,
+    write_queue: Option<WriteQueue>,
+    /// how many pages of a chapter may wait fetched, with `--fetch-buffer`.
+    fetch_buffer: Option<std::num::NonZeroUsize>,
+    /// the seed pages are shuffled by, with `--shuffle`.
+    shuffle: Option<u64>,
+    min_free_space: Option<FreeSpace>,
+    max_decode_memory: Option<DecodeMemory>,
+    max_total_retries: Option<Retries>,
+}
+
+impl Options {
+    /// a request for `url`, accepting what `accept` tells if any.
+    fn get(
+        &self,
+        url: &str,
+        accept: Option<&reqwest::header::HeaderValue>,
+    ) -> reqwest::RequestBuilder {
+        self.request(reqwest::Method::GET, url, accept)
+    }
+
+    /// like `get`, with `method`.
+    fn request(
+        &self,
+        method: reqwest::Method,
+        url: &str,
+        accept: Option<&reqwest::header::HeaderValue>,
+    ) -> reqwest::RequestBuilder {
+        let req = self.client.request(method, url);
+
+        match accept {
+            Some(a) => req.header(reqwest::header::ACCEPT, a),
+            None => req,
+        }
+    }
+
+    /// sends `req`, printing it and the head of its response with
+    /// `--trace-http`.
+    async fn send(&self, req: reqwest::RequestBuilder) -> reqwest::Result<reqwest::Response> {
+        if !self.trace_http {
+            return req.send().await;
+        }
+
+        let (client, req) = req.build_split();
+        let req = req?;
+
+        let mut url = req.url().clone();
+        if url.password().is_some() {
+            let _ = url.set_password(Some("redacted"));
+        }
+
+        info!("> {} {url}", req.method());
+        trace_headers('>', req.headers());
+
+        let res = client.execute(req).await?;
+
+        info!("< {:?} {}", res.version(), res.status());
+        trace_headers('<', res.headers());
+
+        Ok(res)
+    }
+
+    /// held until a response's body is read, as its connection is busy until
+    /// then. waits for the request's turn with `--delay-ms` as well.
+    async fn permit(
+        &self,
+        url: &str,
+    ) -> (
+        Option<tokio::sync::OwnedSemaphorePermit>,
+        Option<tokio::sync::OwnedSemaphorePermit>,
+    ) {
+        let host = match &self.host_limit {
+            Some(l) => l.acquire(url).await,
+            None => None,
+        };
+
+        // a connection to the host first, as that's what may take long
+        let ramp = match &self.ramp_up {
+            Some(r) => r.acquire().await,
+            None => None,
+        };
+
+        if let Some(d) = &self.delay {
+            d.wait().await;
+        }
+
+        (host, ramp)
+    }
+
+    /// fails when writing to `path` would go below `--min-free-space`.
+    fn ensure_free_space(&self, path: &str) -> Result<()> {
+        match &self.min_free_space {
+            Some(f) => f.ensure(path),
+            None => Ok(()),
+        }
+    }
+
+    /// whether a write was refused for `--min-free-space`.
+    fn low_on_space(&self) -> bool {
+        self.min_free_space.as_ref().is_some_and(FreeSpace::low)
+    }
+
+    /// counts a retry, whether it's within `--max-total-retries`.
+    fn retry(&self) -> bool {
+        self.max_total_retries.as_ref().is_none_or(Retries::take)
+    }
+
+    /// whether more retries than `--max-total-retries` were needed.
+    fn unhealthy(&self) -> bool {
+        self.max_total_retries
+            .as_ref()
+            .is_some_and(Retries::exceeded)
+    }
+}
+
+/// prints `headers` for `--trace-http`, each prefixed by `dir`, but not the
+/// values of those carrying credentials.
+fn trace_headers(dir: char, headers: &reqwest::header::HeaderMap) {
+    use reqwest::header::{AUTHORIZATION, COOKIE, PROXY_AUTHORIZATION, SET_COOKIE};
+
+    for (name, value) in headers {
+        let sensitive = [AUTHORIZATION, PROXY_AUTHORIZATION, COOKIE, SET_COOKIE].contains(name);
+
+        if sensitive || value.is_sensitive() {
+            info!("{dir} {name}: <redacted>");
+        } else {
+            info!(
+                "{dir} {name}: {}",
+                String::from_utf8_lossy(value.as_bytes())
+            );
+        }
+    }
+}
+
+/// how pages are encoded as webp. lossless by default.
+#[derive(Clone, Copy)]
+enum Encoding {
+    Lossless,
+    Lossy {
+        quality: f32,
+    },
+    /// lossless for images of up to `colors` colors, lossy otherwise, with
+    /// `--auto-format`.
+    Auto {
+        colors: usize,
+        quality: f32,
+    },
+}
+
+impl Encoding {
+    /// which of lossless or lossy `img` is encoded as with the `channels`
+    /// given, as lossy has no luma.
+    fn pick(self, img: &image::RgbaImage, channels: Channels) -> Self {
+        match self {
+            Self::Auto { .. } if matches!(channels, Channels::Luma | Channels::LumaAlpha) => {
+                Self::Lossless
+            }
+            Self::Auto { colors, quality } => {
+                // stops counting at one color past the threshold
+                let mut seen = std::collections::HashSet::new();
+                let few = img.pixels().all(|p| {
+                    seen.insert(p.0);
+                    seen.len() <= colors
+                });
+
+                match few {
+                    true => Self::Lossless,
+                    false => Self::Lossy { quality },
+                }
+            }
+            e => e,
+        }
+    }
+
+    /// encodes `img` with only the `channels` given, which it has to fit.
+    fn encode(&self, img: &image::RgbaImage, channels: Channels) -> Result<Vec<u8>> {
+        let (raw, color) = channels.pixels(img);
+        let mut bytes = Vec::new();
+
+        match self.pick(img, channels) {
+            Self::Lossless => image::codecs::webp::WebPEncoder::new_lossless(&mut bytes)
+                .encode(&raw, img.width(), img.height(), color)
+                .map_err(Error::Encode)?,
+
+            // the image crate only ships the lossless encoder
+            Self::Lossy { quality } => {
+                let encoder = match channels {
+                    Channels::Rgb => webp::Encoder::from_rgb(&raw, img.width(), img.height()),
+                    Channels::Rgba => webp::Encoder::from_rgba(&raw, img.width(), img.height()),
+                    _ => unreachable!("lossy encoding has no luma"),
+                };
+
+                bytes.extend_from_slice(&encoder.encode(quality))
+            }
+
+            Self::Auto { .. } => unreachable!("auto encoding is picked from"),
+        }
+
+        Ok(bytes)
+    }
+}
+
+impl std::fmt::Display for Encoding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Lossless => write!(f, "lossless"),
+            Self::Lossy { quality } => write!(f, "lossy at quality {quality}"),
+            Self::Auto { colors, quality } => {
+                write!(
+                    f,
+                    "lossless up to {colors} colors, or lossy at quality {quality}"
+                )
+            }
+        }
+    }
+}
+
+/// which channels pages are encoded with. rgba by default.
+#[derive(Default, Clone, Copy, PartialEq, Eq)]
+enum Channels {
+    #[default]
+    Rgba,
+    Rgb,
+    LumaAlpha,
+    Luma,
+}
+
+impl Channels {
+    /// `self`, or the least of the others keeping every detail of `img`.
+    fn fit(self, img: &image::RgbaImage) -> Self {
+        let opaque = || img.pixels().all(|p| p[3] == 255);
+        let gray = || img.pixels().all(|p| p[0] == p[1] && p[1] == p[2]);
+
+        match self {
+            Self::Rgba => Self::Rgba,
+            Self::Rgb if opaque() => Self::Rgb,
+            Self::LumaAlpha if gray() => Self::LumaAlpha,
+            Self::Luma if gray() && opaque() => Self::Luma,
+            Self::Luma if gray() => Self::LumaAlpha,
+            Self::Luma if opaque() => Self::Rgb,
+            _ => Self::Rgba,
+        }
+    }
+
+    /// `self` without alpha, if `img` is fully opaque. that's a pass over it.
+    fn drop_opaque_alpha(self, img: &image::RgbaImage) -> Self {
+        match self {
+            Self::Rgba | Self::LumaAlpha if !img.pixels().all(|p| p[3] == 255) => self,
+            Self::Rgba => Self::Rgb,
+            Self::LumaAlpha => Self::Luma,
+            _ => self,
+        }
+    }
+
+    /// the pixels of `img` with only these channels.
+    fn pixels(self, img: &image::RgbaImage) -> (Vec<u8>, image::ExtendedColorType) {
+        let pick = |channels: &[usize]| {
+            img.pixels()
+                .flat_map(|p| channels.iter().map(|&c| p[c]))
+                .collect()
+        };
+
+        match self {
+            Self::Rgba => (img.as_raw().clone(), image::ExtendedColorType::Rgba8),
+            Self::Rgb => (pick(&[0, 1, 2]), image::ExtendedColorType::Rgb8),
+            Self::LumaAlpha => (pick(&[0, 3]), image::ExtendedColorType::La8),
+            Self::Luma => (pick(&[0]), image::ExtendedColorType::L8),
+        }
+    }
+}
+
+impl std::fmt::Display for Channels {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Rgba => "rgba",
+            Self::Rgb => "rgb",
+            Self::LumaAlpha => "la",
+            Self::Luma => "luma",
+        })
+    }
+}
+
+/// `rgba`, `rgb`, `la` or `luma`, followed by the bits per channel.
+struct BitDepth {
+    channels: Channels,
+    bits: u8,
+}
+
+impl std::str::FromStr for BitDepth {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let at = s.find(|c: char| c.is_ascii_digit()).unwrap_or(s.len());
+        let (channels, bits) = s.split_at(at);
+
+        let channels = match channels {
+            "rgba" => Channels::Rgba,
+            "rgb" => Channels::Rgb,
+            "la" => Channels::LumaAlpha,
+            "luma" => Channels::Luma,
+            _ => return Err("expected one of `rgba`, `rgb`, `la` or `luma`".to_owned()),
+        };
+
+        let bits = match bits {
+            "8" => 8,
+            "16" => 16,
+            _ => return Err("expected 8 or 16 bits".to_owned()),
+        };
+
+        Ok(Self { channels, bits })
+    }
+}
+
+/// where a page came from, embedded into it as xmp with `--embed-metadata`.
+struct Metadata<'a> {
+    /// the urls of the images it was restored from, without credentials.
+    sources: &'a [String],
+    page: (usize, usize),
+    at: std::time::SystemTime,
+    /// of the page printed, with `--page-size`.
+    dpi: Option<u32>,
+}
+
+impl Metadata<'_> {
+    fn xmp(&self) -> String {
+        let escape = |s: &str| {
+            s.replace('&', "&amp;")
+                .replace('<', "&lt;")
+                .replace('>', "&gt;")
+                .replace('"', "&quot;")
+        };
+
+        // the first as `dc:source`, which has room for one, and all of them for
+        // pages made of several
+        let source = match self.sources.first() {
+            Some(s) => format!(r#" dc:source="{}""#, escape(s)),
+            None => String::new(),
+        };
+        let sources = self
+            .sources
+            .iter()
+            .map(|s| format!("<rdf:li>{}</rdf:li>", escape(s)))
+            .collect::<String>();
+
+        // in inches, as the unit 2 tells
+        let resolution = match self.dpi {
+            Some(dpi) => format!(
+                r#" tiff:XResolution="{dpi}/1" tiff:YResolution="{dpi}/1" tiff:ResolutionUnit="2""#
+            ),
+            None => String::new(),
+        };
+
+        format!(
+            concat!(
+                r#"<x:xmpmeta xmlns:x="adobe:ns:meta/">"#,
+                r#"<rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">"#,
+                r#"<rdf:Description rdf:about="""#,
+                r#" xmlns:dc="http://purl.org/dc/elements/1.1/""#,
+                r#" xmlns:xmp="http://ns.adobe.com/xap/1.0/""#,
+                r#" xmlns:tiff="http://ns.adobe.com/tiff/1.0/""#,
+                r#" xmlns:bbbsc="https://github.com/nanai10a/bbb.sc/ns/1.0/""#,
+                r#"{}"#,
+                r#" xmp:CreatorTool="bbbsc {}""#,
+                r#" xmp:CreateDate="{}""#,
+                r#"{}"#,
+                r#" bbbsc:chapter="{}" bbbsc:page="{}">"#,
+                r#"<bbbsc:sources><rdf:Seq>{}</rdf:Seq></bbbsc:sources>"#,
+                r#"</rdf:Description></rdf:RDF></x:xmpmeta>"#,
+            ),
+            source,
+            env!("CARGO_PKG_VERSION"),
+            timestamp(self.at),
+            resolution,
+            self.page.0,
+            self.page.1,
+            sources,
+        )
+    }
+}
+
+/// adds an `ICCP` chunk of `icc` and an `XMP ` chunk of `xmp` to `webp`, turning
+/// it into the extended format if it isn't yet. `None` if `webp` isn't made of
+/// chunks as expected.
+fn extend_webp(
+    webp: &[u8],
+    (width, height): (u32, u32),
+    icc: Option<&[u8]>,
+    xmp: Option<&str>,
+) -> Option<Vec<u8>> {
+    let body = webp
+        .strip_prefix(b"RIFF")?
+        .get(4..)?
+        .strip_prefix(b"WEBP")?;
+
+    let chunk = |fourcc: &[u8], data: &[u8]| {
+        let mut c = fourcc.to_vec();
+        c.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        c.extend_from_slice(data);
+        if data.len() % 2 == 1 {
+            c.push(0);
+        }
+        c
+    };
+
+    let flags = icc.map_or(0, |_| 0x20) | xmp.map_or(0, |_| 0x04);
+
+    let fourcc = body.get(..4)?;
+    let (mut chunks, rest) = match fourcc {
+        b"VP8X" => {
+            // an icc profile has to come right after it
+            let mut vp8x = body.get(..18)?.to_vec();
+            vp8x[8] |= flags;
+            (vp8x, body.get(18..)?)
+        }
+        b"VP8 " | b"VP8L" => {
+            // the alpha flag, which vp8l keeps in its header
+            let alpha = fourcc == b"VP8L" && body.get(8 + 4)? & 0x10 != 0;
+
+            let mut vp8x = vec![flags | if alpha { 0x10 } else { 0 }, 0, 0, 0];
+            vp8x.extend_from_slice(&(width - 1).to_le_bytes()[..3]);
+            vp8x.extend_from_slice(&(height - 1).to_le_bytes()[..3]);
+
+            (chunk(b"VP8X", &vp8x), body)
+        }
+        _ => return None,
+    };
+
+    if let Some(icc) = icc {
+        chunks.extend(chunk(b"ICCP", icc));
+    }
+    chunks.extend_from_slice(rest);
+    if let Some(xmp) = xmp {
+        chunks.extend(chunk(b"XMP ", xmp.as_bytes()));
+    }
+
+    let mut out = b"RIFF".to_vec();
+    out.extend_from_slice(&(chunks.len() as u32 + 4).to_le_bytes());
+    out.extend_from_slice(b"WEBP");
+    out.extend(chunks);
+
+    Some(out)
+}
+
+/// `at` as `YYYY-MM-DDThh:mm:ssZ`.
+fn timestamp(at: std::time::SystemTime) -> String {
+    let secs = at
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let (days, secs) = (secs / 86400, secs % 86400);
+
+    // days since the epoch to a civil date, after howard hinnant's algorithm
+    let z = days + 719468;
+    let (era, doe) = (z / 146097, z % 146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = doy - (153 * mp + 2) / 5 + 1;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 };
+    let y = yoe + era * 400 + u64::from(m <= 2);
+
+    let (hh, mm, ss) = (secs / 3600, secs / 60 % 60, secs % 60);
+
+    format!("{y:04}-{m:02}-{d:02}T{hh:02}:{mm:02}:{ss:02}Z")
+}
+
+/// what to do about output path components that aren't portable, i.e. invalid
+/// on windows.
+#[derive(Default, Clone, Copy)]
+enum Sanitize {
+    #[default]
+    None,
+    Replace,
+    Reject,
+}
+
+impl Sanitize {
+    fn apply(self, path: &str) -> Result<String> {
+        if let Self::None = self {
+            return Ok(path.to_owned());
+        }
+
+        let components = path.split('/').map(|c| {
+            if c.is_empty() || c == "." || c == ".." {
+                return Ok(c.to_owned());
+            }
+
+            let mut s = c
+                .chars()
+                .map(|c| match c {
+                    '<' | '>' | ':' | '"' | '\\' | '|' | '?' | '*' | '\0'..='\x1f' => '_',
+                    c => c,
+                })
+                .collect::<String>();
+
+            if s.ends_with(['.', ' ']) {
+                s.pop();
+                s.push('_');
+            }
+
+            let stem = s.split('.').next().unwrap_or_default().to_ascii_uppercase();
+            let reserved = matches!(stem.as_str(), "CON" | "PRN" | "AUX" | "NUL")
+                || (stem.len() == 4
+                    && (stem.starts_with("COM") || stem.starts_with("LPT"))
+                    && matches!(stem.as_bytes()[3], b'1'..=b'9'));
+            if reserved {
+                s.insert(0, '_');
+            }
+
+            match self {
+                Self::Reject if s != c => Err(Error::Validation(format!(
+                    "`{c}` isn't a portable file name"
+                ))),
+                _ => Ok(s),
+            }
+        });
+
+        Ok(components.collect::<Result<Vec<_>>>()?.join("/"))
+    }
+}
+
+impl std::str::FromStr for Sanitize {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Self::None),
+            "replace" => Ok(Self::Replace),
+            "reject" => Ok(Self::Reject),
+            _ => Err("expected one of `none`, `replace` or `reject`".to_owned()),
+        }
+    }
+}
+
+/// a paper size in millimeters, by name or as `WxH`.
+#[derive(Clone, Copy)]
+struct PageSize {
+    width: f64,
+    height: f64,
+}
+
+impl std::str::FromStr for PageSize {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let (width, height) = match s.to_ascii_lowercase().as_str() {
+            "a4" => (210.0, 297.0),
+            "a5" => (148.0, 210.0),
+            "a6" => (105.0, 148.0),
+            "b5" => (182.0, 257.0),
+            "b6" => (128.0, 182.0),
+            "letter" => (215.9, 279.4),
+            s => {
+                let mm = |n: &str| n.parse::<f64>().ok().filter(|n| *n > 0.0);
+
+                match s.split_once('x').map(|(w, h)| (mm(w), mm(h))) {
+                    Some((Some(w), Some(h))) => (w, h),
+                    _ => return Err(format!("expected a size like `a4` or `128x182`, got `{s}`")),
+                }
+            }
+        };
+
+        Ok(Self { width, height })
+    }
+}
+
+/// what pages are resized to fit with `--page-size`, at `--target-dpi`.
+struct PrintSize {
+    page: PageSize,
+    dpi: u32,
+}
+
+impl PrintSize {
+    /// the largest size of the aspect ratio of `(width, height)` fitting into
+    /// the page.
+    fn fit(&self, (width, height): (u32, u32)) -> (u32, u32) {
+        let px = |mm: f64| mm / 25.4 * self.dpi as f64;
+
+        let scale = f64::min(
+            px(self.page.width) / width as f64,
+            px(self.page.height) / height as f64,
+        );
+
+        let fit = |n: u32| (n as f64 * scale).round().max(1.0) as u32;
+        (fit(width), fit(height))
+    }
+}
+
+/// the grid of thumbnails of a chapter's pages `--contact-sheet` writes.
+struct ContactSheet {
+    columns: usize,
+    /// of a thumbnail, which is as high as its page's aspect ratio gives.
+    width: u32,
+}
+
+impl ContactSheet {
+    fn thumbnail(
+        &self,
+        img: &image::RgbaImage,
+        filter: image::imageops::FilterType,
+    ) -> image::RgbaImage {
+        let height = img.height() as u64 * self.width as u64 / img.width().max(1) as u64;
+        image::imageops::resize(img, self.width, height.max(1) as u32, filter)
+    }
+
+    /// `thumbs` in rows of `columns`, left to right, each row as high as its
+    /// highest, on white.
+    fn compose(&self, thumbs: &[image::RgbaImage]) -> image::RgbaImage {
+        let rows = thumbs.chunks(self.columns).collect::<Vec<_>>();
+        let heights = rows
+            .iter()
+            .map(|r| r.iter().map(|t| t.height()).max().unwrap_or(0))
+            .collect::<Vec<_>>();
+
+        let width = self.width * thumbs.len().min(self.columns) as u32;
+        let mut sheet = image::RgbaImage::from_pixel(
+            width,
+            heights.iter().sum(),
+            image::Rgba([255, 255, 255, 255]),
+        );
+
+        let mut y = 0;
+        for (row, h) in rows.iter().zip(heights) {
+            for (x, t) in (0..).step_by(self.width as usize).zip(*row) {
+                image::imageops::overlay(&mut sheet, t, x, y);
+            }
+            y += h as i64;
+        }
+
+        sheet
+    }
+}
+
+/// what `--icc` converts source images into: `--output-profile`, or srgb.
+struct ColorProfile {
+    profile: Box<qcms::Profile>,
+    /// of `--output-profile`, embedded into pages. none for srgb, which is
+    /// what untagged images are taken to be anyway.
+    icc: Option<Vec<u8>>,
+}
+
+impl ColorProfile {
+    /// `img` converted from the profile `source`, or from srgb without one,
+    /// into this. `None` when it's the same.
+    fn convert(
+        &self,
+        img: &image::DynamicImage,
+        source: Option<&[u8]>,
+    ) -> Result<Option<image::DynamicImage>> {
+        let source = match source {
+            Some(icc) => qcms::Profile::new_from_slice(icc, false).ok_or_else(|| {
+                Error::Validation("its icc profile isn't one qcms supports".to_owned())
+            })?,
+            None if self.icc.is_none() => return Ok(None),
+            None => qcms::Profile::new_sRGB(),
+        };
+
+        let transform = qcms::Transform::new(
+            &source,
+            &self.profile,
+            qcms::DataType::RGBA8,
+            qcms::Intent::default(),
+        )
+        .ok_or_else(|| Error::Validation("its icc profile can't be converted from".to_owned()))?;
+
+        let mut rgba = img.to_rgba8();
+        transform.apply(&mut rgba);
+
+        Ok(Some(image::DynamicImage::ImageRgba8(rgba)))
+    }
+}
+
+/// which consecutive pages `--stitch-spreads` pairs into spreads.
+#[derive(Clone, Copy)]
+enum Spreads {
+    /// 1 and 2, 3 and 4, and so on.
+    Odd,
+    /// 2 and 3, 4 and 5, and so on, leaving 1 (e.g. a cover) alone.
+    Even,
+}
+
+impl Spreads {
+    /// whether page `jdx` is the first half of a spread.
+    fn starts(self, jdx: usize) -> bool {
+        match self {
+            Self::Odd => !jdx.is_multiple_of(2),
+            Self::Even => jdx.is_multiple_of(2),
+        }
+    }
+}
+
+impl std::str::FromStr for Spreads {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "odd" => Ok(Self::Odd),
+            "even" => Ok(Self::Even),
+            _ => Err("expected one of `odd` or `even`".to_owned()),
+        }
+    }
+}
+
+/// what's done of a page whose size is off the one most of its chapter is of,
+/// with `--check-dimensions`.
+#[derive(Clone, Copy)]
+enum DimensionCheck {
+    Warn,
+    Error,
+}
+
+impl std::str::FromStr for DimensionCheck {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "warn" => Ok(Self::Warn),
+            "error" => Ok(Self::Error),
+            _ => Err("expected one of `warn` or `error`".to_owned()),
+        }
+    }
+}
+
+/// the sizes of the pages of a chapter restored so far, for
+/// `--check-dimensions`.
+#[derive(Default)]
+struct Sizes {
+    counts: std::collections::HashMap<(u32, u32), usize>,
+    total: usize,
+    /// pages restored before there was a size to check them against.
+    unchecked: Vec<(usize, (u32, u32))>,
+}
+
+impl Sizes {
+    /// the size most pages are of, once there are at least two of it and it's
+    /// at least half of them.
+    fn mode(&self) -> Option<(u32, u32)> {
+        let (&size, &n) = self.counts.iter().max_by_key(|&(&s, &n)| (n, s))?;
+        (n >= 2 && n * 2 >= self.total).then_some(size)
+    }
+
+    fn add(&mut self, size: (u32, u32)) {
+        *self.counts.entry(size).or_default() += 1;
+        self.total += 1;
+    }
+
+    /// what's wrong with `size` against `mode`, if it's off by more than
+    /// `tolerance` in either direction.
+    fn deviation(size: (u32, u32), mode: (u32, u32), tolerance: f64) -> Option<String> {
+        let off = |a: u32, b: u32| (a as f64 - b as f64).abs() / b.max(1) as f64;
+
+        (off(size.0, mode.0) > tolerance || off(size.1, mode.1) > tolerance).then(|| {
+            format!(
+                "is {}x{}, while its chapter's pages are mostly {}x{}",
+                size.0, size.1, mode.0, mode.1
+            )
+        })
+    }
+}
+
+/// how a ptimg is found in html: the value of `attr`, or the text when `None`, of
+/// the first element matching `selector`.
+struct HtmlExtract {
+    selector: scraper::Selector,
+    attr: Option<String>,
+}
+
+enum Extracted {
+    Json(String),
+    Url(String),
+}
+
+impl HtmlExtract {
+    /// defaults to the `data-ptimg` attribute of the first element having one.
+    fn new(selector: Option<String>, attr: Option<String>) -> std::result::Result<Self, String> {
+        let (selector, attr) = match selector {
+            Some(s) => (s, attr),
+            None => (
+                "[data-ptimg]".to_owned(),
+                Some(attr.unwrap_or_else(|| "data-ptimg".to_owned())),
+            ),
+        };
+
+        let selector = scraper::Selector::parse(&selector).map_err(|e| e.to_string())?;
+
+        Ok(Self { selector, attr })
+    }
+
+    /// the ptimg inlined in `html`, or its url resolved against `base`.
+    fn extract(&self, html: &str, base: &str) -> Option<Extracted> {
+        let html = scraper::Html::parse_document(html);
+        let el = html.select(&self.selector).next()?;
+
+        let value = match &self.attr {
+            Some(attr) => el.value().attr(attr)?.trim().to_owned(),
+            None => el.text().collect::<String>().trim().to_owned(),
+        };
+
+        if value.starts_with('{') {
+            return Some(Extracted::Json(value));
+        }
+
+        match reqwest::Url::parse(base).and_then(|b| b.join(&value)) {
+            Ok(url) => Some(Extracted::Url(url.into())),
+            Err(_) => Some(Extracted::Url(value)),
+        }
+    }
+}
+
+/// `nearest`, `triangle` or `lanczos3`.
+struct TileFilter(image::imageops::FilterType);
+
+impl std::str::FromStr for TileFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        use image::imageops::FilterType;
+
+        match s {
+            "nearest" => Ok(Self(FilterType::Nearest)),
+            "triangle" => Ok(Self(FilterType::Triangle)),
+            "lanczos3" => Ok(Self(FilterType::Lanczos3)),
+            _ => Err("expected one of `nearest`, `triangle` or `lanczos3`".to_owned()),
+        }
+    }
+}
+
+/// `chapter/page=path`: a known-good output of a page.
+struct Baseline {
+    page: (usize, usize),
+    path: String,
+}
+
+impl std::str::FromStr for Baseline {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let Some((page, path)) = s.split_once('=') else {
+            return Err(format!("expected `chapter/page=path`, got `{s}`"));
+        };
+
+        let Pages(pages) = page.parse()?;
+        let (Some(&page), 1) = (pages.iter().next(), pages.len()) else {
+            return Err(format!("expected a single page, got `{page}`"));
+        };
+
+        Ok(Self {
+            page,
+            path: path.to_owned(),
+        })
+    }
+}
+
+/// a cache shared by all pages, keyed by the url of what's cached rather than
+/// by where it's used.
+struct UrlCache {
+    dir: String,
+    entries: std::sync::Mutex<std::collections::HashMap<String, Arc<tokio::sync::Mutex<()>>>>,
+}
+
+impl UrlCache {
+    fn new(dir: String) -> Self {
+        Self {
+            dir,
+            entries: Default::default(),
+        }
+    }
+
+    /// where `url` is cached. fnv-1a, as it has to be stable across builds.
+    fn path(&self, url: &str) -> String {
+        let hash = url.bytes().fold(0xcbf29ce484222325_u64, |h, b| {
+            (h ^ b as u64).wrapping_mul(0x100000001b3)
+        });
+
+        format!("{}/{hash:016x}", self.dir)
+    }
+
+    /// held while `url` is looked up and fetched, so that pages sharing it
+    /// don't fetch it into the same file at once.
+    async fn lock(&self, url: &str) -> tokio::sync::OwnedMutexGuard<()> {
+        let entry = self
+            .entries
+            .lock()
+            .unwrap()
+            .entry(url.to_owned())
+            .or_default()
+            .clone();
+
+        entry.lock_owned().await
+    }
+}
+
+/// caps on how much is downloaded overall, shared by all books, and until when
+/// for `--max-runtime`.
+#[derive(Default)]
+struct Budget {
+    max_bytes: Option<u64>,
+    max_pages: Option<usize>,
+    deadline: Option<std::time::Instant>,
+    bytes: std::sync::atomic::AtomicU64,
+    pages: std::sync::atomic::AtomicUsize,
+    reached: std::sync::atomic::AtomicBool,
+    out_of_time: std::sync::atomic::AtomicBool,
+}
+
+impl Budget {
+    /// reserves a page, unless a cap or the deadline is reached.
+    fn start_page(&self) -> bool {
+        use std::sync::atomic::Ordering::Relaxed;
+
+        if self
+            .deadline
+            .is_some_and(|d| std::time::Instant::now() >= d)
+        {
+            self.out_of_time.store(true, Relaxed);
+            return false;
+        }
+
+        if self.bytes.load(Relaxed) >= self.max_bytes.unwrap_or(u64::MAX) {
+            self.reached.store(true, Relaxed);
+            return false;
+        }
+
+        if self.pages.fetch_add(1, Relaxed) >= self.max_pages.unwrap_or(usize::MAX) {
+            self.cancel_page();
+            self.reached.store(true, Relaxed);
+            return false;
+        }
+
+        true
+    }
+
+    /// gives back the reservation of a page that turned out to be missing.
+    fn cancel_page(&self) {
+        self.pages
+            .fetch_sub(1, std::sync::atomic::Ordering::Relaxed);
+    }
+
+    fn add_bytes(&self, n: usize) {
+        self.bytes
+            .fetch_add(n as u64, std::sync::atomic::Ordering::Relaxed);
+    }
+
+    fn reached(&self) -> bool {
+        self.reached.load(std::sync::atomic::Ordering::Relaxed)
+    }
+
+    fn out_of_time(&self) -> bool {
+        self.out_of_time.load(std::sync::atomic::Ordering::Relaxed)
+    }
+}
+
+impl std::fmt::Display for Budget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use std::sync::atomic::Ordering::Relaxed;
+
+        let bytes = self.bytes.load(Relaxed);
+        let pages = self.pages.load(Relaxed);
+
+        match (self.max_bytes, self.max_pages) {
+            (Some(b), Some(p)) => write!(f, "{bytes}/{b} bytes, {pages}/{p} pages"),
+            (Some(b), None) => write!(f, "{bytes}/{b} bytes"),
+            (None, Some(p)) => write!(f, "{pages}/{p} pages"),
+            (None, None) => write!(f, "unlimited"),
+        }
+    }
+}
+
+/// how much space `--min-free-space` keeps free on the filesystems written to.
+struct FreeSpace {
+    min: u64,
+    low: std::sync::atomic::AtomicBool,
+}
+
+impl FreeSpace {
+    fn new(min: u64) -> Self {
+        Self {
+            min,
+            low: false.into(),
+        }
+    }
+
+    /// fails when writing to `path` would leave less than the minimum free,
+    /// remembering so.
+    fn ensure(&self, path: &str) -> Result<()> {
+        // `path` itself may not exist yet, nor its parents
+        let dir = std::path::Path::new(path)
+            .ancestors()
+            .find(|p| p.is_dir())
+            .unwrap_or(std::path::Path::new("."));
+
+        let free = fs2::available_space(dir)?;
+        if free >= self.min {
+            return Ok(());
+        }
+
+        self.low.store(true, std::sync::atomic::Ordering::Relaxed);
+        Err(std::io::Error::new(
+            std::io::ErrorKind::StorageFull,
+            format!(
+                "only {free} bytes are free on {}, less than {}",
+                dir.display(),
+                self.min
+            ),
+        ))?
+    }
+
+    fn low(&self) -> bool {
+        self.low.load(std::sync::atomic::Ordering::Relaxed)
+    }
+}
+
+/// how much memory `--max-decode-memory` lets the sources being decoded take.
+struct DecodeMemory {
+    permits: Arc<tokio::sync::Semaphore>,
+    total: u32,
+}
+
+impl DecodeMemory {
+    /// a permit is of this many bytes, as no more than `u32::MAX` permits can
+    /// be acquired at once.
+    const UNIT: u64 = 1024;
+
+    fn new(max: u64) -> Self {
+        let total = (max / Self::UNIT)
+            .clamp(1, u32::MAX as u64)
+            .min(tokio::sync::Semaphore::MAX_PERMITS as u64) as u32;
+
+        Self {
+            permits: Arc::new(tokio::sync::Semaphore::new(total as usize)),
+            total,
+        }
+    }
+
+    /// waits until `bytes` are free, or all of them for more than the maximum,
+    /// taking them until the permit is dropped.
+    async fn reserve(&self, opts: &Options, bytes: u64) -> tokio::sync::OwnedSemaphorePermit {
+        let n = bytes.div_ceil(Self::UNIT).min(self.total as u64) as u32;
+        if self.permits.available_permits() < n as usize {
+            verbose!(opts, "waiting for {bytes} bytes to decode into");
+        }
+
+        self.permits
+            .clone()
+            .acquire_many_owned(n)
+            .await
+            .expect("the semaphore is never closed")
+    }
+}
+
+/// how many times `--max-total-retries` lets anything be fetched again, over
+/// the whole run.
+struct Retries {
+    max: u64,
+    used: std::sync::atomic::AtomicU64,
+}
+
+impl Retries {
+    fn new(max: u64) -> Self {
+        Self {
+            max,
+            used: 0.into(),
+        }
+    }
+
+    /// counts a retry, whether it's within the maximum.
+    fn take(&self) -> bool {
+        self.used.fetch_add(1, std::sync::atomic::Ordering::Relaxed) < self.max
+    }
+
+    fn exceeded(&self) -> bool {
+        self.used.load(std::sync::atomic::Ordering::Relaxed) > self.max
+    }
+}
+
+/// fails network fetches at random, at `--simulate-errors`' rate, for testing
+/// how failures are dealt with. the same `--seed` draws the same sequence,
+/// though which fetch gets which draw depends on how they interleave.
+struct SimulatedErrors {
+    rate: f64,
+    state: std::sync::atomic::AtomicU64,
+}
+
+impl SimulatedErrors {
+    fn new(rate: f64, seed: u64) -> Self {
+        Self {
+            rate,
+            state: std::sync::atomic::AtomicU64::new(seed),
+        }
+    }
+
+    /// an error for a fetch of `target`, at the rate.
+    fn draw(&self, target: &str) -> Result<()> {
+        // splitmix64
+        let mut z = self
+            .state
+            .fetch_add(0x9e37_79b9_7f4a_7c15, std::sync::atomic::Ordering::Relaxed)
+            .wrapping_add(0x9e37_79b9_7f4a_7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+
+        if ((z >> 11) as f64 / (1u64 << 53) as f64) < self.rate {
+            let msg = format!("simulated failure fetching {target}");
+            return Err(std::io::Error::other(msg))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// `key=ext`: a resource fetched for every page, whose url is `--target`
+/// substituted by `ext` rather than `jpg`, and which coords refer to by `key`.
+struct Kind {
+    key: String,
+    ext: String,
+}
+
+impl std::str::FromStr for Kind {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let Some((key, ext)) = s.split_once('=') else {
+            return Err(format!("{s} is not key=ext"));
+        };
+
+        if key.is_empty() || ext.is_empty() || ext.contains(['/', '\\']) {
+            return Err(format!("{s} has no key or no file extension"));
+        }
+        if matches!(ext, "ptimg.json" | "html" | "jpg" | "webp") {
+            return Err(format!("{ext} is already fetched or written for pages"));
+        }
+
+        Ok(Self {
+            key: key.to_owned(),
+            ext: ext.to_owned(),
+        })
+    }
+}
+
+/// a probability, from 0 to 1.
+struct Rate(f64);
+
+impl std::str::FromStr for Rate {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let rate = s.parse::<f64>().map_err(|e| e.to_string())?;
+        if !(0.0..=1.0).contains(&rate) {
+            return Err(format!("{rate} is not within 0 and 1"));
+        }
+
+        Ok(Self(rate))
+    }
+}
+
+/// media ranges, e.g. `image/webp,image/*;q=0.8`.
+struct Accept(reqwest::header::HeaderValue);
+
+impl std::str::FromStr for Accept {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        for range in s.split(',') {
+            let mime = range.split(';').next().unwrap_or_default().trim();
+
+            match mime.split_once('/') {
+                Some((ty, sub)) if !ty.is_empty() && !sub.is_empty() && !sub.contains('/') => {}
+                _ => return Err(format!("expected `type/subtype`, got `{}`", range.trim())),
+            }
+        }
+
+        s.parse().map(Self).map_err(|e| format!("{e}"))
+    }
+}
+
+/// what `--coords-override` has for a page: coords replacing those of the
+/// first view of its ptimg, or a whole view, which stands in for the ptimg when
+/// it's missing.
+#[derive(Clone, serde::Deserialize)]
+#[serde(untagged)]
+enum Override {
+    Coords(Vec<String>),
+    View(bbbsc::View),
+}
+
+impl Override {
+    /// reads json mapping `chapter/page` to overrides, whose coords are in
+    /// fractions when `normalized`.
+    fn load(
+        path: &str,
+        normalized: bool,
+    ) -> std::result::Result<std::collections::HashMap<(usize, usize), Self>, String> {
+        let json = std::fs::read(path).map_err(|e| format!("{path}: {e}"))?;
+        let map = serde_json::from_slice::<std::collections::HashMap<String, Self>>(&json)
+            .map_err(|e| format!("{path}: {e}"))?;
+
+        map.into_iter()
+            .map(|(page, o)| {
+                let Pages(pages) = page.parse()?;
+                let (Some(&page), 1) = (pages.iter().next(), pages.len()) else {
+                    return Err(format!("expected a single page, got `{page}`"));
+                };
+
+                let coords = match &o {
+                    Self::Coords(c) => c,
+                    Self::View(v) => &v.coords,
+                };
+                for c in coords {
+                    coord_key(c, normalized).map_err(|e| format!("{}/{}: {e}", page.0, page.1))?;
+                }
+
+                Ok((page, o))
+            })
+            .collect()
+    }
+
+    /// applies this to `pt`.
+    fn apply(self, mut pt: Ptimg) -> Result<Ptimg> {
+        match (self, pt.views.first_mut()) {
+            (Self::Coords(coords), Some(v)) => v.coords = coords,
+            (Self::Coords(_), None) => {
+                return Err(Error::Validation("ptimg has no views".to_owned()));
+            }
+            (Self::View(view), Some(v)) => *v = view,
+            (Self::View(view), None) => pt.views.push(view),
+        }
+
+        Ok(pt)
+    }
+}
+
+/// a ptimg made up of just `view`, for a page whose own is missing.
+fn standalone(view: bbbsc::View) -> Ptimg {
+    Ptimg {
+        ptimg_version: 1,
+        resources: Default::default(),
+        views: vec![view],
+        title: None,
+    }
+}
+
+/// caps how many requests may be in flight to the same host at once.
+struct HostLimit {
+    max: usize,
+    hosts: std::sync::Mutex<std::collections::HashMap<String, Arc<tokio::sync::Semaphore>>>,
+}
+
+impl HostLimit {
+    fn new(max: usize) -> Self {
+        Self {
+            max,
+            hosts: Default::default(),
+        }
+    }
+
+    async fn acquire(&self, url: &str) -> Option<tokio::sync::OwnedSemaphorePermit> {
+        let url = reqwest::Url::parse(url).ok()?;
+        let host = format!("{}:{}", url.host_str()?, url.port_or_known_default()?);
+
+        let sem = self
+            .hosts
+            .lock()
+            .unwrap()
+            .entry(host)
+            .or_insert_with(|| Arc::new(tokio::sync::Semaphore::new(self.max)))
+            .clone();
+
+        sem.acquire_owned().await.ok()
+    }
+}
+
+/// how many requests `--ramp-up` lets be in flight at once, from a single one
+/// at the start up to the most there may be at its end.
+struct RampUp {
+    permits: Arc<tokio::sync::Semaphore>,
+}
+
+impl RampUp {
+    /// starts letting one request through, and one more every so often, for
+    /// `max` after `period`.
+    fn start(max: usize, period: std::time::Duration) -> Self {
+        let permits = Arc::new(tokio::sync::Semaphore::new(1));
+
+        if max > 1 {
+            let step = period.div_f64((max - 1) as f64);
+            let more = permits.clone();
+
+            tokio::spawn(async move {
+                let start = tokio::time::Instant::now();
+
+                for n in 1..max as u32 {
+                    tokio::time::sleep_until(start + step * n).await;
+                    more.add_permits(1);
+                }
+            });
+        }
+
+        Self { permits }
+    }
+
+    async fn acquire(&self) -> Option<tokio::sync::OwnedSemaphorePermit> {
+        self.permits.clone().acquire_owned().await.ok()
+    }
+}
+
+/// spaces requests out by `--delay-ms`, across all of them.
+struct Delay {
+    every: std::time::Duration,
+    next: tokio::sync::Mutex<tokio::time::Instant>,
+}
+
+impl Delay {
+    fn new(every: std::time::Duration) -> Self {
+        Self {
+            every,
+            next: tokio::time::Instant::now().into(),
+        }
+    }
+
+    /// waits until `every` after the request before this one, or none if it was
+    /// long ago. turns are taken in order, without waiting while holding on to
+    /// the next one.
+    async fn wait(&self) {
+        let at = {
+            let mut next = self.next.lock().await;
+            let at = (*next).max(tokio::time::Instant::now());
+            *next = at + self.every;
+            at
+        };
+
+        tokio::time::sleep_until(at).await;
+    }
+}
+
+/// a duration given in (fractional) seconds on the command line.
+#[derive(Clone, Copy)]
+struct Secs(std::time::Duration);
+
+impl std::str::FromStr for Secs {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let secs = s.parse::<f64>().map_err(|e| e.to_string())?;
+        let dur = std::time::Duration::try_from_secs_f64(secs).map_err(|e| e.to_string())?;
+
+        Ok(Self(dur))
+    }
+}
+
+/// a comma separated list of `chapter/page`, e.g. `1/3,2/10`.
+#[derive(Default)]
+struct Pages(std::collections::HashSet<(usize, usize)>);
+
+impl Pages {
+    fn contains(&self, page: (usize, usize)) -> bool {
+        self.0.contains(&page)
+    }
+}
+
+impl std::str::FromStr for Pages {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        s.split(',')
+            .map(|p| {
+                let Some((chapter, page)) = p.split_once('/') else {
+                    return Err(format!("expected `chapter/page`, got `{p}`"));
+                };
+
+                let chapter = chapter.trim().parse().map_err(|e| format!("{e}"))?;
+                let page = page.trim().parse().map_err(|e| format!("{e}"))?;
+
+                Ok((chapter, page))
+            })
+            .collect::<std::result::Result<_, _>>()
+            .map(Self)
+    }
+}
+
+/// `host:port:addr` for `--resolve`, as curl takes it: `host` connected to at
+/// `addr` rather than what it resolves to, which may be several separated by
+/// `,`, an ipv6 one in brackets.
+struct Resolve {
+    host: String,
+    addrs: Vec<std::net::SocketAddr>,
+}
+
+impl std::str::FromStr for Resolve {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let mut parts = s.splitn(3, ':');
+        let (Some(host), Some(port), Some(addrs)) = (parts.next(), parts.next(), parts.next())
+        else {
+            return Err("expected `host:port:addr`".to_owned());
+        };
+
+        if host.is_empty() {
+            return Err("host is empty".to_owned());
+        }
+        let port = port
+            .parse::<u16>()
+            .map_err(|e| format!("port {port}: {e}"))?;
+
+        let addrs = addrs
+            .split(',')
+            .map(|a| {
+                let ip = a.strip_prefix('[').and_then(|a| a.strip_suffix(']'));
+                ip.unwrap_or(a)
+                    .parse::<std::net::IpAddr>()
+                    .map(|ip| std::net::SocketAddr::new(ip, port))
+                    .map_err(|e| format!("addr {a}: {e}"))
+            })
+            .collect::<std::result::Result<_, _>>()?;
+
+        Ok(Self {
+            host: host.to_owned(),
+            addrs,
+        })
+    }
+}
+
+/// `user:pass` for http basic auth.
+struct BasicAuth(reqwest::header::HeaderValue);
+
+impl std::str::FromStr for BasicAuth {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        use base64::Engine;
+
+        match s.split_once(':') {
+            Some((user, _)) if !user.is_empty() => {}
+            _ => return Err("expected `user:pass`".to_owned()),
+        }
+
+        let encoded = base64::engine::general_purpose::STANDARD.encode(s);
+        let mut value = reqwest::header::HeaderValue::try_from(format!("Basic {encoded}"))
+            .map_err(|e| e.to_string())?;
+        value.set_sensitive(true);
+
+        Ok(Self(value))
+    }
+}
 
 #[tokio::main]
 async fn main() {
-    let mut args = parse_args();
+    let mut argv = std::env::args().skip(1).peekable();
+
+    if argv.peek().map(String::as_str) == Some("self-test") {
+        argv.next();
+        return self_test(parse_args(argv));
+    }
+
+    if argv.peek().map(String::as_str) == Some("validate") {
+        argv.next();
+        return validate(parse_args(argv));
+    }
+
+    if argv.peek().map(String::as_str) == Some("formats") {
+        return list_formats();
+    }
+
+    if argv.peek().map(String::as_str) == Some("list-cache") {
+        argv.next();
+        return list_cache(parse_args(argv));
+    }
+
+    if argv.peek().map(String::as_str) == Some("transcode") {
+        argv.next();
+        return transcode(parse_args(argv)).await;
+    }
+
+    let mut args = parse_args(argv);
+
+    // templates and paths, which scripts may want to put together from the
+    // environment
+    const EXPANDED: &[&str] = &[
+        "target",
+        "dist",
+        "ptimg",
+        "html",
+        "title-url",
+        "chapter-dir",
+        "url-cache",
+        "cookie-jar",
+        "error-report",
+        "manifest",
+        "tar",
+        "coords-override",
+        "compare-baseline",
+        "dump-tiles",
+        "debug-visualize",
+        "log-file",
+        "output-profile",
+        "export-coords",
+    ];
+
+    for &key in EXPANDED {
+        for v in args.get_mut(key).into_iter().flatten() {
+            match bbbsc::expand_env(v, |name| std::env::var(name).ok()) {
+                Ok(expanded) => *v = expanded,
+                Err(e) => {
+                    error!("couldn't recognize {}: {e}", key.replace('-', "_"));
+                    std::process::exit(1)
+                }
+            }
+        }
+    }
+
+    let started = std::time::Instant::now();
+    let config = configuration(&args);
+
+    // first of all, for everything to be logged
+    take_arg!(log_file? from args);
+    if let Some::<String>(path) = log_file {
+        match LogFile::open(&path) {
+            Ok(f) => _ = LOG_FILE.set(std::sync::Mutex::new(f)),
+            Err(e) => {
+                error!("couldn't open log_file: {e}");
+                std::process::exit(1)
+            }
+        }
+    }
+
+    let mut missing = Vec::new();
+
+    // with `--stdin`, books are read from there instead
+    take_arg!(stdin from args or false);
+    take_arg!(target* from args);
+    take_arg!(dist* from args);
+    let target: Vec<String> = target;
+    let dist: Vec<String> = dist;
+
+    if !stdin {
+        if target.is_empty() {
+            missing.push("target");
+        }
+        if dist.is_empty() {
+            missing.push("dist");
+        }
+    }
+
+    if !missing.is_empty() {
+        error!("couldn't recognize {}", missing.join(", "));
+        std::process::exit(1)
+    }
+
+    if stdin && !(target.is_empty() && dist.is_empty()) {
+        error!("stdin and target or dist are exclusive");
+        std::process::exit(1)
+    }
+
+    if target.len() != dist.len() {
+        error!("every target needs its dist");
+        std::process::exit(1)
+    }
+
+    take_arg!(ptimg? from args);
+    take_arg!(html? from args);
+    take_arg!(html_selector? from args);
+    take_arg!(html_attr? from args);
+    take_arg!(chapter_concurrency from args or std::num::NonZeroUsize::MIN);
+    take_arg!(ramp_up? from args);
+    take_arg!(delay_ms? from args);
+    take_arg!(page_timeout? from args);
+    take_arg!(basic_auth? from args);
+    take_arg!(resolve* from args);
+    let encoding = take_encoding(&mut args);
+    take_arg!(max_connections_per_host? from args);
+    let max_connections_per_host = max_connections_per_host.map(std::num::NonZeroUsize::get);
+    take_arg!(debug_pages from args or Pages::default());
+    take_arg!(dump_tiles? from args);
+    take_arg!(debug_visualize? from args);
+    take_arg!(http1_only from args or false);
+    take_arg!(http2_only from args or false);
+    take_arg!(verbose from args or false);
+    take_arg!(sanitize from args or Sanitize::default());
+    take_arg!(no_cache from args or false);
+    take_arg!(offline from args or false);
+    take_arg!(url_cache? from args);
+    take_arg!(tile_filter? from args);
+    take_arg!(wrap from args or false);
+    take_arg!(lenient from args or false);
+    take_arg!(retry_failed_pages from args or false);
+    take_arg!(error_report? from args);
+    take_arg!(manifest? from args);
+    take_arg!(manifest_timings from args or false);
+    take_arg!(compare_baseline? from args);
+    take_arg!(max_bytes? from args);
+    take_arg!(max_pages? from args);
+    take_arg!(max_runtime? from args);
+    take_arg!(min_free_space? from args);
+    take_arg!(max_total_retries? from args);
+    take_arg!(embed_metadata from args or false);
+    take_arg!(icc from args or false);
+    take_arg!(force from args or false);
+    take_arg!(state from args or false);
+    take_arg!(head_first from args or false);
+    take_arg!(export_coords? from args);
+    take_arg!(yes from args or false);
+    take_arg!(output_profile? from args);
+    take_arg!(accept? from args);
+    take_arg!(chapter_pad from args or 2);
+    take_arg!(page_pad from args or 4);
+    take_arg!(detect_pad from args or false);
+    take_arg!(cookie_jar? from args);
+    take_arg!(chapter_gap_tolerance from args or 0);
+    take_arg!(chapter_dir from args or "{index}".to_owned());
+    take_arg!(flat from args or false);
+    take_arg!(clean_output from args or false);
+    take_arg!(all_views from args or false);
+    take_arg!(kind* from args);
+    take_arg!(refetch_on_size_mismatch from args or false);
+    take_arg!(verify_pairing from args or false);
+    take_arg!(check_dimensions? from args);
+    take_arg!(dimension_tolerance from args or 0.1);
+    take_arg!(resource_concurrency from args or std::num::NonZeroUsize::new(8).unwrap());
+    take_arg!(simulate_errors? from args);
+    take_arg!(seed? from args);
+    take_arg!(title_url? from args);
+    take_arg!(dedupe_output from args or false);
+    take_arg!(coords_override? from args);
+    take_arg!(max_image_pixels from args or 100_000_000);
+    take_arg!(max_decode_memory? from args);
+    take_arg!(checksums from args or false);
+    take_arg!(bit_depth? from args);
+    take_arg!(drop_opaque_alpha from args or false);
+    take_arg!(normalized_coords from args or false);
+    take_arg!(trace_http from args or false);
+    take_arg!(stitch_spreads? from args);
+    take_arg!(right_to_left from args or false);
+    take_arg!(page_size? from args);
+    take_arg!(target_dpi? from args);
+    take_arg!(write_queue? from args);
+    take_arg!(fetch_buffer? from args);
+    take_arg!(shuffle from args or false);
+    take_arg!(contact_sheet from args or false);
+    take_arg!(contact_sheet_columns from args or std::num::NonZeroUsize::new(6).unwrap());
+    take_arg!(contact_sheet_width from args or std::num::NonZeroU32::new(160).unwrap());
+    take_arg!(cbz from args or false);
+    take_arg!(tar? from args);
+    take_arg!(archive_chunk_size? from args);
+
+    // the same for everything random, e.g. `--simulate-errors` and `--shuffle`
+    let seed: u64 = seed.unwrap_or_else(|| {
+        let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH);
+        now.map_or(0, |d| d.as_nanos() as u64)
+    });
+    if shuffle && stitch_spreads.is_some() {
+        error!("shuffle and stitch_spreads are exclusive");
+        std::process::exit(1)
+    }
+
+    // a page tried again would be taken for the first half of a spread
+    if retry_failed_pages && stitch_spreads.is_some() {
+        error!("retry_failed_pages and stitch_spreads are exclusive");
+        std::process::exit(1)
+    }
+
+    let icc = match (icc, output_profile) {
+        (true, None) => Some(ColorProfile {
+            profile: qcms::Profile::new_sRGB(),
+            icc: None,
+        }),
+        (true, Some::<String>(path)) => {
+            let profile = std::fs::read(&path)
+                .map_err(|e| e.to_string())
+                .and_then(|icc| match qcms::Profile::new_from_slice(&icc, false) {
+                    Some(profile) => Ok(ColorProfile {
+                        profile,
+                        icc: Some(icc),
+                    }),
+                    None => Err("not an icc profile qcms supports".to_owned()),
+                });
+
+            match profile {
+                Ok(p) => Some(p),
+                Err(e) => {
+                    error!("couldn't load {path}: {e}");
+                    std::process::exit(1)
+                }
+            }
+        }
+        (false, Some(_)) => {
+            error!("output_profile needs icc");
+            std::process::exit(1)
+        }
+        (false, None) => None,
+    };
+
+    let print_size = match (page_size, target_dpi) {
+        (Some(page), dpi) => Some(PrintSize {
+            page,
+            dpi: dpi.unwrap_or(300),
+        }),
+        (None, Some::<u32>(_)) => {
+            error!("target_dpi needs page_size");
+            std::process::exit(1)
+        }
+        (None, None) => None,
+    };
+
+    let (write_queue, writes) = match write_queue {
+        Some::<std::num::NonZeroUsize>(depth) => {
+            let (tx, rx) = tokio::sync::mpsc::channel(depth.get());
+            (Some(WriteQueue(tx)), Some(rx))
+        }
+        None => (None, None),
+    };
+
+    let coords_override = match coords_override {
+        Some::<String>(path) => match Override::load(&path, normalized_coords) {
+            Ok(o) => o,
+            Err(e) => {
+                error!("couldn't load coords_override: {e}");
+                std::process::exit(1)
+            }
+        },
+        None => Default::default(),
+    };
+
+    let pad = Pad {
+        chapter: chapter_pad,
+        page: page_pad,
+    };
+    take_arg!(baseline_tolerance from args or 2.0);
+
+    let mut restore = bbbsc::RestoreOptions::default();
+    if let Some(TileFilter(f)) = tile_filter {
+        restore.filter = f;
+    }
+    restore.wrap = wrap;
+
+    if dump_tiles.is_some() && debug_pages.0.is_empty() {
+        error!("dump_tiles needs debug_pages to select pages");
+        std::process::exit(1)
+    }
+
+    if debug_visualize.is_some() && debug_pages.0.is_empty() {
+        error!("debug_visualize needs debug_pages to select pages");
+        std::process::exit(1)
+    }
+
+    if (dump_tiles.is_some() || debug_visualize.is_some()) && !writable("png") {
+        error!("png can't be written, as the image crate was built without its png feature: rebuild with it enabled to dump_tiles or debug_visualize");
+        std::process::exit(1)
+    }
+
+    if !(0.0..).contains(&dimension_tolerance) {
+        error!("couldn't recognize dimension_tolerance: {dimension_tolerance} is out of 0..");
+        std::process::exit(1)
+    }
+
+    if manifest_timings && manifest.is_none() {
+        error!("manifest_timings needs manifest");
+        std::process::exit(1)
+    }
+
+    if archive_chunk_size.is_some() && !cbz {
+        error!("archive_chunk_size needs cbz");
+        std::process::exit(1)
+    }
+
+    let mut channels = Channels::default();
+    if let Some(BitDepth { channels: c, bits }) = bit_depth {
+        channels = c;
+
+        if bits != 8 {
+            warning!("webp has 8 bits per channel, falling back to {c}8");
+        }
+
+        if let (Encoding::Lossy { .. }, Channels::Luma | Channels::LumaAlpha) = (&encoding, c) {
+            channels = if c == Channels::Luma {
+                Channels::Rgb
+            } else {
+                Channels::Rgba
+            };
+            warning!("lossy webp has no luma, falling back to {channels}8");
+        }
+    }
+
+    let mut headers = reqwest::header::HeaderMap::new();
+    if let Some(BasicAuth(auth)) = basic_auth {
+        headers.insert(reqwest::header::AUTHORIZATION, auth);
+    }
+
+    let cookies = match &cookie_jar {
+        Some(path) => match std::fs::File::open(path) {
+            Ok(f) => cookie_store::serde::json::load(std::io::BufReader::new(f)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Default::default()),
+            Err(e) => Err(e.into()),
+        },
+        None => Ok(Default::default()),
+    };
+
+    let cookies = match cookies {
+        Ok(c) => Arc::new(reqwest_cookie_store::CookieStoreMutex::new(c)),
+        Err(e) => {
+            error!("couldn't load cookie_jar: {e}");
+            std::process::exit(1)
+        }
+    };
+
+    let mut client = reqwest::Client::builder()
+        .default_headers(headers)
+        .cookie_provider(cookies.clone());
+    if let Some(max) = max_connections_per_host {
+        client = client.pool_max_idle_per_host(max);
+    }
+
+    // those of a host given several times are all tried, in order
+    let mut resolved = std::collections::BTreeMap::<_, Vec<_>>::new();
+    for Resolve { host, addrs } in resolve {
+        resolved.entry(host).or_default().extend(addrs);
+    }
+    for (host, addrs) in resolved {
+        client = client.resolve_to_addrs(&host, &addrs);
+    }
+
+    match (http1_only, http2_only) {
+        (false, false) => {}
+        (true, false) => client = client.http1_only(),
+        (false, true) => client = client.http2_prior_knowledge(),
+        (true, true) => {
+            error!("http1_only and http2_only are exclusive");
+            std::process::exit(1)
+        }
+    }
+
+    let client = match client.build() {
+        Ok(c) => c,
+        Err(e) => {
+            error!("couldn't build client: {e}");
+            std::process::exit(1)
+        }
+    };
+
+    let html_extract = match HtmlExtract::new(html_selector, html_attr) {
+        Ok(h) => h,
+        Err(e) => {
+            error!("couldn't recognize html_selector: {e}");
+            std::process::exit(1)
+        }
+    };
+
+    if no_cache && url_cache.is_some() {
+        error!("no_cache and url_cache are exclusive");
+        std::process::exit(1)
+    }
+
+    let tar = match tar {
+        Some::<String>(path) => match Tar::create(&path, shuffle || retry_failed_pages).await {
+            Ok(t) => Some(t),
+            Err(e) => {
+                error!("couldn't create {path}: {e}");
+                std::process::exit(1)
+            }
+        },
+        None => None,
+    };
+
+    if offline && no_cache {
+        error!("offline and no_cache are exclusive");
+        std::process::exit(1)
+    }
+
+    if offline && detect_pad {
+        error!("detect_pad probes upstream, so is exclusive with offline");
+        std::process::exit(1)
+    }
+
+    if offline && compare_baseline.is_some() {
+        error!("compare_baseline fetches upstream, so is exclusive with offline");
+        std::process::exit(1)
+    }
+
+    // titles aren't cached, so chapters would go elsewhere than they did
+    if offline && chapter_dir.contains("{title}") {
+        error!("chapter_dir with {{title}} isn't cached, so is exclusive with offline");
+        std::process::exit(1)
+    }
+
+    if let Some(dir) = &url_cache {
+        if let Err(e) = bbbsc::create_dir_all(dir).await {
+            error!("couldn't create {dir}: {e}");
+            std::process::exit(1)
+        }
+    }
+
+    let mut opts = Options {
+        client,
+        host_limit: max_connections_per_host.map(HostLimit::new),
+        // at most a request for each resource of a page of each chapter
+        ramp_up: ramp_up.map(|Secs(t)| {
+            let max = chapter_concurrency.get() * resource_concurrency.get();
+            RampUp::start(max, t)
+        }),
+        delay: delay_ms.map(|ms| Delay::new(std::time::Duration::from_millis(ms))),
+        page_timeout: page_timeout.map(|Secs(t)| t),
+        encoding,
+        debug_pages,
+        dump_tiles,
+        debug_visualize,
+        verbose,
+        sanitize,
+        html_extract,
+        no_cache,
+        offline,
+        missed: Default::default(),
+        url_cache: url_cache.map(UrlCache::new),
+        restore,
+        normalized_coords,
+        lenient,
+        retry_failed_pages,
+        manifest: manifest.is_some(),
+        manifest_timings,
+        budget: Budget {
+            max_bytes,
+            max_pages,
+            deadline: max_runtime.map(|Secs(t)| started + t),
+            ..Default::default()
+        },
+        embed_metadata,
+        accept: accept.map(|Accept(a)| a),
+        trace_http,
+        stitch_spreads,
+        right_to_left,
+        print_size,
+        write_queue,
+        fetch_buffer,
+        detect_pad,
+        chapter_gap_tolerance,
+        chapter_dir,
+        flat,
+        clean_output,
+        all_views,
+        kinds: kind,
+        refetch_on_size_mismatch,
+        verify_pairing,
+        check_dimensions,
+        dimension_tolerance,
+        icc,
+        force,
+        head_first,
+        export_coords,
+        contact_sheet: contact_sheet.then(|| ContactSheet {
+            columns: contact_sheet_columns.get(),
+            width: contact_sheet_width.get(),
+        }),
+        cbz,
+        archive_chunk_size: archive_chunk_size.map(std::num::NonZeroUsize::get),
+        tar,
+        resource_concurrency: resource_concurrency.get(),
+        simulate_errors: simulate_errors.map(|Rate(r)| {
+            warning!("simulating fetch failures at a rate of {r}, seeded by {seed}");
+            SimulatedErrors::new(r, seed)
+        }),
+        shuffle: shuffle.then(|| {
+            info!("shuffling pages, seeded by {seed}");
+            seed
+        }),
+        dedupe_output: dedupe_output.then(Dedupe::default),
+        min_free_space: min_free_space.map(FreeSpace::new),
+        max_decode_memory: max_decode_memory.map(DecodeMemory::new),
+        max_total_retries: max_total_retries.map(Retries::new),
+        state: state.then_some(config),
+        coords_override,
+        max_image_pixels,
+        checksums,
+        channels,
+        drop_opaque_alpha,
+    };
+
+    if stdin && compare_baseline.is_some() {
+        error!("compare_baseline needs target, so is exclusive with stdin");
+        std::process::exit(1)
+    }
+
+    // there's no asking with stdin taken, nor telling what would be overwritten
+    if stdin && opts.force && !yes {
+        error!("force with stdin needs yes");
+        std::process::exit(1)
+    }
+
+    if let Some(baseline) = compare_baseline {
+        let templates = Templates {
+            target: target[0].clone(),
+            ptimg: ptimg.clone(),
+            html: html.clone(),
+            title: title_url.clone(),
+            pad,
+        };
+
+        // the check is only meaningful against what upstream serves right now
+        opts.no_cache = true;
+        let res = check_baseline(&opts, templates, &dist[0], &baseline).await;
+        opts.no_cache = no_cache;
+
+        match res {
+            Ok(diff) if diff <= baseline_tolerance => {
+                info!("{}: matches its baseline ({diff:.2})", baseline.path);
+            }
+            Ok(diff) => {
+                error!(
+                    "{}: diverges from its baseline ({diff:.2} > {baseline_tolerance}), \
+                     upstream may have changed its scrambling",
+                    baseline.path,
+                );
+                std::process::exit(1)
+            }
+            Err(e) => {
+                error!("{}: {e}", baseline.path);
+                std::process::exit(1)
+            }
+        }
+    }
+
+    if opts.force && !yes {
+        let mut existing = 0;
+        for dist in &dist {
+            match opts.sanitize.apply(&format!("./{dist}")) {
+                Ok(path) => existing += count_pages(std::path::Path::new(&path)),
+                Err(e) => warning!("{dist}: {e}"),
+            }
+        }
+
+        if existing > 0 {
+            match confirm(&format!("overwrite {existing} existing pages?")) {
+                Some(true) => {}
+                Some(false) => {
+                    error!("not overwriting {existing} existing pages");
+                    std::process::exit(1)
+                }
+                None => {
+                    error!("force would overwrite {existing} existing pages, which needs yes without a terminal");
+                    std::process::exit(1)
+                }
+            }
+        }
+    }
+
+    let opts = Arc::new(opts);
+    let mut books = 0;
+
+    if let Some(rx) = writes {
+        tokio::spawn(writer(opts.clone(), rx));
+    }
+
+    let mut total = Summary::default();
+    let mut failed = false;
+
+    let mut pairs = target.into_iter().zip(dist);
+    let mut lines = stdin.then(|| {
+        use tokio::io::AsyncBufReadExt;
+        (tokio::io::BufReader::new(tokio::io::stdin()).lines(), 0)
+    });
+
+    loop {
+        if opts.unhealthy() {
+            break;
+        }
+
+        // one line at a time, as a script writes them
+        let (target, dist) = match &mut lines {
+            None => match pairs.next() {
+                Some(pair) => pair,
+                None => break,
+            },
+            Some((lines, n)) => match lines.next_line().await {
+                Ok(Some(line)) => {
+                    *n += 1;
+
+                    match stdin_pair(&line) {
+                        Ok(Some(pair)) => pair,
+                        Ok(None) => continue,
+                        Err(e) => {
+                            error!("stdin line {n}: {e}");
+                            failed = true;
+                            continue;
+                        }
+                    }
+                }
+                Ok(None) => break,
+                Err(e) => {
+                    error!("couldn't read stdin: {e}");
+                    failed = true;
+                    break;
+                }
+            },
+        };
+        books += 1;
+
+        let templates = Templates {
+            target,
+            ptimg: ptimg.clone(),
+            html: html.clone(),
+            title: title_url.clone(),
+            pad,
+        };
+
+        let res = run(templates, &dist, chapter_concurrency.get(), opts.clone()).await;
+
+        // what's held of a book failing is written all the same, as it's on disk
+        let res = match &opts.tar {
+            Some(t) => t.book_done().await.and(res),
+            None => res,
+        };
+
+        match res {
+            Ok(summary) => {
+                info!("{dist}: {summary}");
+                total += summary;
+            }
+            Err(e) => {
+                error!("{dist}: {e}");
+                failed = true;
+            }
+        }
+    }
+
+    if let Some(t) = &opts.tar {
+        if let Err(e) = t.finish().await {
+            error!("couldn't finish the tar: {e}");
+            failed = true;
+        }
+    }
+
+    if let Some::<String>(path) = cookie_jar {
+        let mut json = Vec::new();
+        let res = cookie_store::serde::json::save_incl_expired_and_nonpersistent(
+            &cookies.lock().unwrap(),
+            &mut json,
+        );
+
+        let res = match res {
+            Ok(()) => tokio::fs::write(&path, json).await.map_err(Into::into),
+            Err(e) => Err(e),
+        };
+
+        if let Err(e) = res {
+            error!("couldn't save {path}: {e}");
+            failed = true;
+        }
+    }
+
+    if books > 1 {
+        info!("total: {total}");
+    }
+
+    if !total.failures.is_empty() {
+        report_failures(&total.failures);
+        failed = true;
+    }
+
+    if let Some::<std::path::PathBuf>(path) = error_report {
+        let json = serde_json::to_vec_pretty(&total.failures).expect("failures are serializable");
+
+        if let Err(e) = tokio::fs::write(&path, json).await {
+            error!("couldn't write {}: {e}", path.display());
+            failed = true;
+        }
+    }
+
+    if let Some::<std::path::PathBuf>(path) = manifest {
+        let json = serde_json::to_vec_pretty(&total.written).expect("manifests are serializable");
+
+        if let Err(e) = tokio::fs::write(&path, json).await {
+            error!("couldn't write {}: {e}", path.display());
+            failed = true;
+        }
+    }
+
+    if let Some(r) = opts.max_total_retries.as_ref().filter(|r| r.exceeded()) {
+        error!(
+            "stopped after more than {} retries in total, as the server appears unhealthy",
+            r.max
+        );
+        std::process::exit(1)
+    }
+
+    if failed {
+        std::process::exit(1)
+    }
+
+    if opts.budget.out_of_time() {
+        info!("stopped as the time limit was reached, run again to go on");
+        std::process::exit(3)
+    }
+
+    if opts.budget.reached() {
+        info!("stopped as the budget was reached: {}", opts.budget);
+        std::process::exit(2)
+    }
+
+    if opts.low_on_space() {
+        info!("stopped as free space ran low, run again once there's more");
+        std::process::exit(2)
+    }
+}
+
+/// the target and dist of a `--stdin` line, separated by a tab, or `None` for
+/// a blank one.
+fn stdin_pair(line: &str) -> std::result::Result<Option<(String, String)>, String> {
+    let line = line.strip_suffix('\r').unwrap_or(line);
+    if line.trim().is_empty() {
+        return Ok(None);
+    }
+
+    match line.split('\t').collect::<Vec<_>>()[..] {
+        [target, dist] if !target.is_empty() && !dist.is_empty() => {
+            Ok(Some((target.to_owned(), dist.to_owned())))
+        }
+        [_, _] => Err("target or dist is empty".to_owned()),
+        _ => Err("expected a target and a dist separated by a single tab".to_owned()),
+    }
+}
+
+/// how many pages (`.webp` but contact sheets) are in `dir` and below.
+fn count_pages(dir: &std::path::Path) -> usize {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return 0;
+    };
+
+    entries
+        .flatten()
+        .map(|e| match e.file_type() {
+            Ok(t) if t.is_dir() => count_pages(&e.path()),
+            Ok(_) => {
+                let name = e.file_name();
+                let name = name.to_string_lossy();
+                (name.ends_with(".webp") && !name.ends_with("contact-sheet.webp")) as usize
+            }
+            Err(_) => 0,
+        })
+        .sum()
+}
+
+/// asks `question` on a terminal, whether the answer is yes. `None` without a
+/// terminal to ask on.
+fn confirm(question: &str) -> Option<bool> {
+    use std::io::IsTerminal;
+
+    if !std::io::stdin().is_terminal() {
+        return None;
+    }
+
+    eprint!("{question} [y/N] ");
+
+    let mut answer = String::new();
+    if std::io::stdin().read_line(&mut answer).is_err() {
+        return Some(false);
+    }
+
+    Some(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// checks the ptimg at `--ptimg`, or on stdin, for `Ptimg::problems`,
+/// reporting all of them.
+fn validate(mut args: std::collections::HashMap<String, Vec<String>>) {
+    take_arg!(ptimg? from args);
+
+    let (name, bytes) = match ptimg {
+        Some::<String>(path) if path != "-" => {
+            let bytes = std::fs::read(&path);
+            (path, bytes)
+        }
+        _ => {
+            use std::io::Read;
+
+            let mut bytes = Vec::new();
+            let res = std::io::stdin().read_to_end(&mut bytes).map(|_| bytes);
+            ("stdin".to_owned(), res)
+        }
+    };
+
+    let pt = match bytes
+        .map_err(Error::from)
+        .and_then(|b| Ptimg::from_slice(&b))
+    {
+        Ok(pt) => pt,
+        Err(e) => {
+            error!("{name}: {e}");
+            std::process::exit(1)
+        }
+    };
+
+    let problems = pt.problems();
+    if problems.is_empty() {
+        info!("{name}: ok");
+        return;
+    }
+
+    for p in &problems {
+        error!("{name}: {p}");
+    }
+    info!("{name}: {} problems", problems.len());
+
+    std::process::exit(1)
+}
+
+/// what's cached for a page, as `list-cache` reports it.
+#[derive(serde::Serialize)]
+struct CachedPage {
+    chapter: String,
+    page: String,
+    /// whether its ptimg (or html) isn't cached, which what else is was
+    /// fetched for.
+    orphaned: bool,
+    files: Vec<CachedFile>,
+}
+
+#[derive(serde::Serialize)]
+struct CachedFile {
+    name: String,
+    /// `ptimg`, `html`, `source`, or `partial` for what's still being fetched.
+    kind: &'static str,
+    size: u64,
+}
+
+/// reports what's cached in the books' directories at `--dist`, grouped by
+/// chapter and page, or with `--json`, prints it as json.
+fn list_cache(mut args: std::collections::HashMap<String, Vec<String>>) {
+    let mut missing = Vec::new();
+
+    take_arg!(dist+ from args else missing);
+
+    if !missing.is_empty() {
+        error!("couldn't recognize {}", missing.join(", "));
+        std::process::exit(1)
+    }
+
+    take_arg!(sanitize from args or Sanitize::default());
+    take_arg!(kind* from args);
+    take_arg!(json from args or false);
+
+    let mut books = Vec::new();
+
+    for dist in dist {
+        let path = match sanitize.apply(&format!("./{dist}")) {
+            Ok(path) => path,
+            Err(e) => {
+                error!("{dist}: {e}");
+                std::process::exit(1)
+            }
+        };
+
+        let mut found = std::collections::BTreeMap::<_, Vec<_>>::new();
+        if let Err(e) = cached_files(std::path::Path::new(&path), &kind, &mut found) {
+            error!("{path}: {e}");
+            std::process::exit(1)
+        }
+
+        let pages = found
+            .into_iter()
+            .map(|((chapter, sep, page), mut files)| {
+                files.sort_by(|a, b| a.name.cmp(&b.name));
+                let orphaned = !files.iter().any(|f| matches!(f.kind, "ptimg" | "html"));
+                (
+                    sep,
+                    CachedPage {
+                        chapter,
+                        page,
+                        orphaned,
+                        files,
+                    },
+                )
+            })
+            .collect::<Vec<_>>();
+
+        books.push((path, pages));
+    }
+
+    if json {
+        let json = books
+            .into_iter()
+            .map(|(path, pages)| {
+                let pages = pages.into_iter().map(|(_, p)| p).collect::<Vec<_>>();
+                serde_json::json!({ "path": path, "pages": pages })
+            })
+            .collect::<Vec<_>>();
+
+        let json = serde_json::to_string_pretty(&json).expect("cache listings are serializable");
+        println!("{json}");
+        return;
+    }
+
+    for (path, pages) in &books {
+        let (mut files, mut bytes, mut partial, mut orphaned) = (0, 0, 0, 0);
+
+        for (sep, p) in pages {
+            let name = format!("{path}/{}{sep}{}", p.chapter, p.page);
+            let size = p.files.iter().map(|f| f.size).sum::<u64>();
+            info!("{name}: {} files, {size} bytes", p.files.len());
+
+            for f in p.files.iter().filter(|f| f.kind == "partial") {
+                warning!("{name}: {} is partial, of {} bytes", f.name, f.size);
+                partial += 1;
+            }
+
+            if p.orphaned {
+                warning!("{name}: orphaned, as its ptimg isn't cached");
+                orphaned += 1;
+            }
+
+            files += p.files.len();
+            bytes += size;
+        }
+
+        info!(
+            "{path}: {} pages, {files} files, {bytes} bytes cached, {partial} partial, {orphaned} orphaned",
+            pages.len()
+        );
+    }
+}
+
+/// collects what's cached in `dir`, by chapter, the separator of its prefix
+/// (`/` for chapter directories, `_` with `--flat`) and page. pages
+/// themselves, their checksums and contact sheets aren't cached, so left out.
+fn cached_files(
+    dir: &std::path::Path,
+    kinds: &[Kind],
+    found: &mut std::collections::BTreeMap<(String, &'static str, String), Vec<CachedFile>>,
+) -> std::io::Result<()> {
+    for e in std::fs::read_dir(dir)? {
+        let e = e?;
+        let name = e.file_name().to_string_lossy().into_owned();
+
+        if e.file_type()?.is_dir() {
+            for f in std::fs::read_dir(e.path())? {
+                let f = f?;
+                let file = f.file_name().to_string_lossy().into_owned();
+
+                if let Some((page, kind)) = cached_kind(&file, kinds) {
+                    let key = (name.clone(), "/", page.to_owned());
+                    let size = f.metadata()?.len();
+                    found.entry(key).or_default().push(CachedFile {
+                        name: file,
+                        kind,
+                        size,
+                    });
+                }
+            }
+
+            continue;
+        }
+
+        // the chapter's prefix may contain `_` itself, as from a title
+        let split = name
+            .match_indices('_')
+            .map(|(i, _)| (&name[..i], &name[i + 1..]))
+            .find(|(_, rest)| is_page_file(rest, kinds));
+
+        if let Some((chapter, rest)) = split {
+            if let Some((page, kind)) = cached_kind(rest, kinds) {
+                let key = (chapter.to_owned(), "_", page.to_owned());
+                let size = e.metadata()?.len();
+                found
+                    .entry(key)
+                    .or_default()
+                    .push(CachedFile { name, kind, size });
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// the page and kind of a file named after it, as `is_page_file` tells, if it's
+/// cached rather than written. a numbered `.webp` is taken as a view rather
+/// than a resource.
+fn cached_kind<'a>(name: &'a str, kinds: &[Kind]) -> Option<(&'a str, &'static str)> {
+    if !is_page_file(name, kinds) {
+        return None;
+    }
+
+    let (page, rest) = name.split_once('.')?;
+    let (rest, partial) = match rest
+        .strip_suffix(".part.validator")
+        .or_else(|| rest.strip_suffix(".part"))
+    {
+        Some(rest) => (rest, true),
+        None => (rest, false),
+    };
+
+    let kind = match rest {
+        "ptimg.json" => "ptimg",
+        "html" => "html",
+        "jpg" => "source",
+        _ if kinds.iter().any(|k| k.ext == rest) => "source",
+        _ => match rest.split_once('.') {
+            Some((_, "webp" | "webp.sha256")) | None => return None,
+            Some(_) => "source",
+        },
+    };
+
+    Some((page, if partial { "partial" } else { kind }))
+}
+
+/// how pages are encoded, from `--webp-lossless`, `--quality`, `--auto-format`
+/// and `--auto-format-colors`.
+fn take_encoding(mut args: &mut std::collections::HashMap<String, Vec<String>>) -> Encoding {
+    take_arg!(webp_lossless from args or false);
+    take_arg!(quality? from args);
+    take_arg!(auto_format from args or false);
+    take_arg!(auto_format_colors from args or 256);
+
+    if let Some(q) = quality.filter(|q| !(0.0..=100.0).contains(q)) {
+        error!("couldn't recognize quality: {q} is out of 0..=100");
+        std::process::exit(1)
+    }
+
+    let encoding = match (webp_lossless, quality, auto_format) {
+        (true, Some(_), _) => {
+            error!("webp_lossless and quality are exclusive");
+            std::process::exit(1)
+        }
+        (true, None, true) => {
+            error!("webp_lossless and auto_format are exclusive");
+            std::process::exit(1)
+        }
+        (false, q, true) => Encoding::Auto {
+            colors: auto_format_colors,
+            quality: q.unwrap_or(90.0),
+        },
+        (_, None, false) => Encoding::Lossless,
+        (false, Some(q), false) => Encoding::Lossy { quality: q },
+    };
+
+    // lossy webp is written by the webp crate, which is always there
+    if !matches!(encoding, Encoding::Lossy { .. }) && !writable("webp") {
+        error!("lossless webp can't be written, as the image crate was built without its webp feature: rebuild with it enabled, or pass quality for lossy webp");
+        std::process::exit(1)
+    }
+
+    encoding
+}
+
+/// whether the `image` crate was built to write the format named `name`.
+fn writable(name: &str) -> bool {
+    bbbsc::formats().iter().any(|f| f.name == name && f.encode)
+}
+
+/// re-encodes the pages written to the books' directories at `--dist` as
+/// `take_encoding` tells, in place.
+async fn transcode(mut args: std::collections::HashMap<String, Vec<String>>) {
+    let mut missing = Vec::new();
+
+    take_arg!(dist+ from args else missing);
+
+    if !missing.is_empty() {
+        error!("couldn't recognize {}", missing.join(", "));
+        std::process::exit(1)
+    }
+
+    take_arg!(sanitize from args or Sanitize::default());
+    take_arg!(max_image_pixels from args or 100_000_000);
+    let encoding = take_encoding(&mut args);
+
+    let mut failed = 0;
+
+    for dist in dist {
+        let path = match sanitize.apply(&format!("./{dist}")) {
+            Ok(path) => path,
+            Err(e) => {
+                error!("{dist}: {e}");
+                std::process::exit(1)
+            }
+        };
+
+        let pages = match written_pages(std::path::Path::new(&path)) {
+            Ok(pages) => pages,
+            Err(e) => {
+                error!("{path}: {e}");
+                std::process::exit(1)
+            }
+        };
+
+        let (mut before, mut after, mut done) = (0, 0, 0);
+
+        for page in &pages {
+            let page = page.to_string_lossy();
+
+            match transcode_page(&page, encoding, max_image_pixels).await {
+                Ok((b, a)) => {
+                    info!("{page}: {b} to {a} bytes");
+                    before += b;
+                    after += a;
+                    done += 1;
+                }
+                Err(e) => {
+                    error!("{page}: {e}");
+                    failed += 1;
+                }
+            }
+        }
+
+        info!("{path}: {done} pages, {before} to {after} bytes");
+    }
+
+    if failed > 0 {
+        error!("{failed} pages failed");
+        std::process::exit(1)
+    }
+}
+
+/// the pages written in `dir`, views and contact sheets included, as laid out
+/// by default or with `--flat`.
+fn written_pages(dir: &std::path::Path) -> std::io::Result<Vec<std::path::PathBuf>> {
+    let written = |name: &str| name.ends_with(".webp") && is_page_file(name, &[]);
+    let mut pages = Vec::new();
+
+    for e in std::fs::read_dir(dir)? {
+        let e = e?;
+        let name = e.file_name().to_string_lossy().into_owned();
+
+        if e.file_type()?.is_dir() {
+            for f in std::fs::read_dir(e.path())? {
+                let f = f?;
+                if written(&f.file_name().to_string_lossy()) {
+                    pages.push(f.path());
+                }
+            }
+
+            continue;
+        }
+
+        // the chapter's prefix may contain `_` itself, as from a title
+        if name
+            .match_indices('_')
+            .any(|(i, _)| written(&name[i + 1..]))
+        {
+            pages.push(e.path());
+        }
+    }
+
+    pages.sort();
+    Ok(pages)
+}
+
+/// re-encodes the page at `path`, keeping its alpha if it has any, and
+/// replaces it along with its checksum, if there's one. gives back its size
+/// before and after.
+async fn transcode_page(path: &str, encoding: Encoding, max_pixels: u64) -> Result<(u64, u64)> {
+    let bytes = tokio::fs::read(path).await?;
+    let img = bbbsc::decode(&bytes, max_pixels)?;
+
+    let channels = match img.color().has_alpha() {
+        true => Channels::Rgba,
+        false => Channels::Rgb,
+    };
+    let img = img.into_rgba8();
+    let encoded = encoding.encode(&img, channels)?;
+
+    // replaced at once, so a page is never seen half written
+    let part = format!("{path}.part");
+    tokio::fs::write(&part, &encoded).await?;
+    tokio::fs::rename(&part, path).await?;
+
+    if tokio::fs::try_exists(format!("{path}.sha256")).await? {
+        write_checksum(path, &encoded).await?;
+    }
+
+    Ok((bytes.len() as u64, encoded.len() as u64))
+}
+
+/// shuffles `items` as `seed` determines.
+fn shuffle<T>(items: &mut [T], seed: u64) {
+    // xorshift64*, as the shuffle only has to vary
+    let mut state = seed | 1;
+    let mut next = || {
+        state ^= state >> 12;
+        state ^= state << 25;
+        state ^= state >> 27;
+        state.wrapping_mul(0x2545f4914f6cdd1d)
+    };
+
+    for i in (1..items.len()).rev() {
+        items.swap(i, (next() % (i as u64 + 1)) as usize);
+    }
+}
+
+/// tells which image formats source images may be of, and which the `image`
+/// crate could write, as it was compiled.
+fn list_formats() {
+    for f in bbbsc::formats() {
+        let support = match (f.decode, f.encode) {
+            (true, true) => "decode, encode",
+            (true, false) => "decode",
+            (false, true) => "encode",
+            (false, false) => "none",
+        };
+
+        info!("{} ({}): {support}", f.name, f.mime);
+    }
+
+    info!("pages are written as webp, whatever their sources are");
+
+    if !writable("webp") {
+        warning!("lossless webp can't be written as built, only lossy with quality");
+    }
+}
+
+/// scrambles `--input` into a random permutation of its tiles, then descrambles
+/// it by a ptimg describing that, which has to give back the input.
+fn self_test(mut args: std::collections::HashMap<String, Vec<String>>) {
+    use bbbsc::{Replacer, Vec2};
+
+    let mut missing = Vec::new();
+
+    take_arg!(input from args else missing);
+
+    if !missing.is_empty() {
+        error!("couldn't recognize {}", missing.join(", "));
+        std::process::exit(1)
+    }
+
+    take_arg!(tile_size from args or std::num::NonZeroU32::new(16).unwrap());
+    take_arg!(seed? from args);
+
+    let t = tile_size.get();
+    let seed: u64 = seed.unwrap_or_else(|| {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as u64)
+    });
+
+    let original = match image::open(&input) {
+        Ok(img) => img.to_rgba8(),
+        Err(e) => {
+            error!("couldn't open {input}: {e}");
+            std::process::exit(1)
+        }
+    };
+
+    let (w, h) = original.dimensions();
+    let (cols, rows) = (w / t, h / t);
+
+    let mut perm = (0..cols * rows).collect::<Vec<_>>();
+    shuffle(&mut perm, seed);
+
+    let at = |n: u32| Vec2::new(n % cols * t, n / cols * t);
+
+    // the edges not filling a whole tile stay in place
+    let mut scrambled = original.clone();
+    let mut coords = Vec::new();
+
+    if w > cols * t {
+        coords.push(format!(
+            "i:{},0+{},{h}>{},0",
+            cols * t,
+            w - cols * t,
+            cols * t
+        ));
+    }
+    if h > rows * t {
+        coords.push(format!(
+            "i:0,{}+{},{}>0,{}",
+            rows * t,
+            cols * t,
+            h - rows * t,
+            rows * t
+        ));
+    }
+
+    for (i, &p) in perm.iter().enumerate() {
+        let (from, to) = (at(i as u32), at(p));
+
+        let scramble = Replacer::new(
+            Vec2::new(t, t),
+            Vec2::new(from.x, from.y),
+            Vec2::new(to.x as i64, to.y as i64),
+        );
+        scramble.apply(&original, &mut scrambled);
+
+        coords.push(format!("i:{},{}+{t},{t}>{},{}", to.x, to.y, from.x, from.y));
+    }
+
+    let json = serde_json::json!({
+        "ptimg-version": 1,
+        "resources": { "i": { "src": input, "width": w, "height": h } },
+        "views": [{ "width": w, "height": h, "coords": coords }],
+    });
+
+    let scrambled = image::DynamicImage::ImageRgba8(scrambled);
+    let restored =
+        Ptimg::from_slice(json.to_string().as_bytes()).and_then(|pt| pt.restore(|_| &scrambled));
+
+    match restored.as_deref() {
+        Ok([restored]) if *restored == original => {
+            info!(
+                "self-test passed: {} tiles of {t}x{t}, seed {seed}",
+                perm.len()
+            );
+        }
+        Ok(_) => {
+            error!("self-test failed: restored image differs, seed {seed}");
+            std::process::exit(1)
+        }
+        Err(e) => {
+            error!("self-test failed: {e}, seed {seed}");
+            std::process::exit(1)
+        }
+    }
+}
+
+/// a page that couldn't be downloaded.
+#[derive(serde::Serialize)]
+struct Failure {
+    book: Arc<str>,
+    chapter: usize,
+    page: usize,
+    kind: &'static str,
+    message: String,
+}
+
+/// a page written, for `--manifest`.
+#[derive(serde::Serialize)]
+struct WrittenPage {
+    book: Arc<str>,
+    chapter: usize,
+    page: usize,
+    /// its views, or a spread it's the first half of, included.
+    files: Vec<String>,
+    #[serde(flatten)]
+    timing: Option<Timing>,
+}
+
+/// how long a page took to fetch, decode and encode, each as timed by the task
+/// doing it, and the bytes fetched for it and written of it, for
+/// `--manifest-timings`.
+#[derive(Default, serde::Serialize)]
+struct Timing {
+    #[serde(rename = "fetch_ms", serialize_with = "millis")]
+    fetch: std::time::Duration,
+    #[serde(rename = "decode_ms", serialize_with = "millis")]
+    decode: std::time::Duration,
+    #[serde(rename = "encode_ms", serialize_with = "millis")]
+    encode: std::time::Duration,
+    source_bytes: u64,
+    output_bytes: u64,
+}
+
+/// `d` in milliseconds, to the microsecond.
+fn millis<S: serde::Serializer>(
+    d: &std::time::Duration,
+    s: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    s.serialize_f64(d.as_micros() as f64 / 1000.0)
+}
+
+/// what's recorded of a page for `--manifest` as it's done.
+#[derive(Default)]
+struct Record {
+    files: Vec<String>,
+    timing: Timing,
+}
+
+/// prints `failures` grouped by book and chapter.
+fn report_failures(failures: &[Failure]) {
+    let mut grouped = std::collections::BTreeMap::<_, Vec<_>>::new();
+    for f in failures {
+        grouped.entry((&f.book, f.chapter)).or_default().push(f);
+    }
+
+    info!("failed pages:");
+
+    for ((book, chapter), mut failures) in grouped {
+        failures.sort_by_key(|f| f.page);
+
+        info!("  {book} {chapter:02}:");
+        for f in failures {
+            info!("    {:04}: {}", f.page, f.message);
+        }
+    }
+}
+
+/// where a book's resources come from. `{}` is substituted in order by the
+/// dist, the chapter and the page, and the one left in `target` finally by the
+/// resource kind.
+#[derive(Clone)]
+struct Templates {
+    target: String,
+    ptimg: Option<String>,
+    html: Option<String>,
+    title: Option<String>,
+    pad: Pad,
+}
+
+impl Templates {
+    fn substitute(&self, value: &str) -> Self {
+        Self {
+            target: substitute(&self.target, value),
+            ptimg: self.ptimg.as_deref().map(|p| substitute(p, value)),
+            html: self.html.as_deref().map(|h| substitute(h, value)),
+            title: self.title.as_deref().map(|t| substitute(t, value)),
+            pad: self.pad,
+        }
+    }
+
+    fn chapter(&self, idx: usize) -> Self {
+        self.substitute(&format!("{idx:0w$}", w = self.pad.chapter))
+    }
+
+    fn page(&self, jdx: usize) -> Self {
+        self.substitute(&format!("{jdx:0w$}", w = self.pad.page))
+    }
+
+    /// the url the first fetch of a page is made to.
+    fn first_url(&self) -> String {
+        match (&self.html, &self.ptimg) {
+            (Some(html), _) => html.clone(),
+            (None, Some(ptimg)) => ptimg.clone(),
+            (None, None) => self.target.replacen("{}", "ptimg.json", 1),
+        }
+    }
+}
+
+/// how many digits chapters and pages are zero-padded to in urls.
+#[derive(Clone, Copy)]
+struct Pad {
+    chapter: usize,
+    page: usize,
+}
+
+impl Pad {
+    /// probes the first page of the first chapter with up to 4 digits each,
+    /// for when the padding isn't known. it's only trusted when exactly one
+    /// combination is found; otherwise, `self` is kept.
+    async fn detect(self, opts: &Options, templates: &Templates) -> Self {
+        let mut found = Vec::new();
+
+        for chapter in 1..=4 {
+            for page in 1..=4 {
+                let pad = Self { chapter, page };
+                let templates = Templates {
+                    pad,
+                    ..templates.clone()
+                };
+                let url = templates.chapter(1).page(1).first_url();
+
+                if url.starts_with("data:") {
+                    return self;
+                }
+
+                let _permit = opts.permit(&url).await;
+                if let Ok(r) = opts.send(opts.get(&url, None)).await {
+                    if r.status().is_success() {
+                        found.push(pad);
+                    }
+                }
+            }
+        }
+
+        match found[..] {
+            [pad] => {
+                verbose!(opts, "detected padding: {} and {}", pad.chapter, pad.page);
+                pad
+            }
+            _ => {
+                warning!(
+                    "padding is ambiguous ({} found), falling back to {} and {}",
+                    found.len(),
+                    self.chapter,
+                    self.page,
+                );
+                self
+            }
+        }
+    }
+}
+
+/// what a run downloaded. `pages` counts every page found, including the
+/// failed ones.
+#[derive(Default)]
+struct Summary {
+    chapters: usize,
+    pages: usize,
+    /// of `pages`, those `--state` tells were done before.
+    resumed: usize,
+    failures: Vec<Failure>,
+    /// with `--manifest`, by chapter and page within a book.
+    written: Vec<WrittenPage>,
+}
+
+impl std::ops::AddAssign for Summary {
+    fn add_assign(&mut self, rhs: Self) {
+        self.chapters += rhs.chapters;
+        self.pages += rhs.pages;
+        self.resumed += rhs.resumed;
+        self.failures.extend(rhs.failures);
+        self.written.extend(rhs.written);
+    }
+}
+
+impl std::fmt::Display for Summary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} chapters, {} pages", self.chapters, self.pages)?;
+
+        if self.resumed > 0 {
+            write!(f, ", {} of them done before", self.resumed)?;
+        }
+
+        if !self.failures.is_empty() {
+            write!(f, " ({} failed)", self.failures.len())?;
+        }
+
+        Ok(())
+    }
+}
+
+/// a book's progress for `--state`, kept in `.bbb-state.json` in its directory
+/// and saved as each page is done.
+struct State {
+    path: String,
+    saved: tokio::sync::Mutex<Saved>,
+}
+
+/// what's in `.bbb-state.json`.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct Saved {
+    /// the sha-256 of the configuration pages were last written with.
+    config: String,
+    started: String,
+    updated: String,
+    /// by chapter, the last page of those done from its first one on.
+    done: std::collections::BTreeMap<usize, usize>,
+    /// by chapter, the pages done past those, as with `--lenient` skipping a
+    /// failed one, or `--shuffle`.
+    #[serde(default)]
+    ahead: std::collections::BTreeMap<usize, std::collections::BTreeSet<usize>>,
+}
+
+impl State {
+    /// reads the state of the book at `dir`, warning when it was saved under
+    /// another configuration than `config`. `fresh` starts it over.
+    async fn open(dir: &str, config: &str, fresh: bool) -> Result<Self> {
+        use sha2::Digest;
+
+        let path = format!("{dir}/.bbb-state.json");
+        let config = sha2::Sha256::digest(config)
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect::<String>();
+
+        let saved = match tokio::fs::read(&path).await {
+            Ok(b) if !fresh => Some(serde_json::from_slice::<Saved>(&b)?),
+            Ok(_) => None,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e.into()),
+        };
+
+        let saved = match saved {
+            Some(mut saved) => {
+                if saved.config != config {
+                    warning!("{path}: saved under another configuration, pages done before are skipped still, force redoes them");
+                    saved.config = config;
+                }
+
+                saved
+            }
+            None => {
+                let now = timestamp(std::time::SystemTime::now());
+
+                Saved {
+                    config,
+                    started: now.clone(),
+                    updated: now,
+                    done: Default::default(),
+                    ahead: Default::default(),
+                }
+            }
+        };
+
+        Ok(Self {
+            path,
+            saved: saved.into(),
+        })
+    }
+
+    /// whether page `jdx` of chapter `idx` was done before.
+    async fn done(&self, idx: usize, jdx: usize) -> bool {
+        let saved = self.saved.lock().await;
+
+        saved.done.get(&idx).is_some_and(|&n| jdx <= n)
+            || saved.ahead.get(&idx).is_some_and(|a| a.contains(&jdx))
+    }
+
+    /// records page `jdx` of chapter `idx` as done, and saves it. failing to
+    /// is only logged, as it only costs doing the page again.
+    async fn complete(&self, idx: usize, jdx: usize) {
+        let mut guard = self.saved.lock().await;
+        let saved = &mut *guard;
+
+        let ahead = saved.ahead.entry(idx).or_default();
+        ahead.insert(jdx);
+
+        let mut last = saved.done.get(&idx).copied().unwrap_or(0);
+        while ahead.remove(&(last + 1)) {
+            last += 1;
+        }
+
+        if ahead.is_empty() {
+            saved.ahead.remove(&idx);
+        }
+        if last > 0 {
+            saved.done.insert(idx, last);
+        }
+        saved.updated = timestamp(std::time::SystemTime::now());
+
+        let json = serde_json::to_vec_pretty(saved).expect("states are serializable");
+
+        // replaced at once, for an interrupted run to leave the previous one
+        let part = format!("{}.part", self.path);
+        let res = match tokio::fs::write(&part, json).await {
+            Ok(()) => tokio::fs::rename(&part, &self.path).await,
+            Err(e) => Err(e),
+        };
+
+        if let Err(e) = res {
+            warning!("couldn't save {}: {e}", self.path);
+        }
+    }
+}
+
+/// downloads chapters until the first one whose first page is missing, or with
+/// `--chapter-gap-tolerance`, until more than that many consecutive ones are.
+///
+/// up to `concurrency` chapters are in flight at once, so they may complete out
+/// of order. a chapter yielding no page (completing such a run) marks the end of
+/// the book: no further chapter is started anymore, while the ones already in
+/// flight are allowed to finish (and kept, if they happen to have pages).
+async fn run(
+    templates: Templates,
+    dist: &str,
+    concurrency: usize,
+    opts: Arc<Options>,
+) -> Result<Summary> {
+    let path = opts.sanitize.apply(&format!("./{dist}"))?;
+    let mut templates = templates.substitute(dist);
+    let book = Arc::<str>::from(dist);
+
+    if opts.detect_pad {
+        templates.pad = templates.pad.detect(&opts, &templates).await;
+    }
+
+    let state = match &opts.state {
+        Some(config) => {
+            let config = format!("{config}target={}\n", templates.target);
+            Some(Arc::new(State::open(&path, &config, opts.force).await?))
+        }
+        None => None,
+    };
+
+    let mut chapters = tokio::task::JoinSet::new();
+    let mut ended = false;
+    let mut empty = std::collections::BTreeSet::new();
+    let mut summary = Summary::default();
+
+    for idx in 1.. {
+        while chapters.len() >= concurrency || (ended && !chapters.is_empty()) {
+            let (idx, chapter): (usize, Summary) = match chapters.join_next().await {
+                Some(Ok(res)) => res?,
+                Some(Err(e)) => std::panic::resume_unwind(e.into_panic()),
+                None => unreachable!(),
+            };
+
+            if let Some(t) = &opts.tar {
+                t.chapter_done(idx).await?;
+            }
+
+            if chapter.pages == 0 {
+                empty.insert(idx);
+
+                let before = (1..idx).rev().take_while(|i| empty.contains(i)).count();
+                let after = (idx + 1..).take_while(|i| empty.contains(i)).count();
+
+                if before + 1 + after > opts.chapter_gap_tolerance {
+                    ended = true;
+                }
+            } else {
+                summary += chapter;
+            }
+        }
+
+        if ended {
+            break;
+        }
+
+        let templates = templates.chapter(idx);
+
+        let chapter = run_chapter(
+            book.clone(),
+            idx,
+            path.clone(),
+            templates,
+            opts.clone(),
+            state.clone(),
+        );
+        chapters.spawn(async move { chapter.await.map(|c| (idx, c)) });
+    }
+
+    // as chapters complete out of order
+    summary.written.sort_by_key(|w| (w.chapter, w.page));
+
+    Ok(summary)
+}
+
+/// downloads pages until the first missing one into its directory in `book`'s
+/// `path`, or with `--flat`, right into `path` prefixed by what would be the
+/// directory. a page failing otherwise fails the chapter, unless `--lenient`,
+/// where it's recorded and skipped.
+async fn run_chapter(
+    book: Arc<str>,
+    idx: usize,
+    path: String,
+    templates: Templates,
+    opts: Arc<Options>,
+    state: Option<Arc<State>>,
+) -> Result<Summary> {
+    let dir = chapter_dir(&opts, &path, idx, &templates).await?;
+    let prefix = if opts.flat {
+        if let Some(p) = std::path::Path::new(&dir).parent() {
+            bbbsc::create_dir_all(p).await?;
+        }
+        format!("{dir}_")
+    } else {
+        bbbsc::create_dir_all(&dir).await?;
+        format!("{dir}/")
+    };
+
+    if opts.clean_output {
+        let found = unexpected_files(&prefix, &opts.kinds).await?;
+
+        if !found.is_empty() {
+            return Err(Error::Validation(format!(
+                "{} files bbbsc doesn't write: {}",
+                found.len(),
+                found.join(", ")
+            )));
+        }
+    }
+
+    let mut summary = Summary {
+        chapters: 1,
+        ..Default::default()
+    };
+
+    let mut fail = |page, e: Error| {
+        summary.failures.push(Failure {
+            book: book.clone(),
+            chapter: idx,
+            page,
+            kind: e.kind(),
+            message: e.to_string(),
+        });
+    };
+
+    let mut pending = Pending::default();
+
+    // with `--shuffle`, the pages found by probing go first, in random order,
+    // and the rest as usual, so that the chapter ends just as it would
+    let mut shuffled = Vec::new();
+    if let Some(seed) = opts.shuffle {
+        shuffled.extend(1..=probe(&opts, &prefix, &templates).await);
+        shuffle(&mut shuffled, seed ^ idx as u64);
+    }
+    let confirmed = shuffled.len();
+
+    // with `--write-queue` or `--stitch-spreads`, it's only known which pages
+    // are written once the chapter is done
+    let deferred = opts.write_queue.is_some() || opts.stitch_spreads.is_some();
+    let mut done = Vec::new();
+    let mut unwritten = false;
+
+    // with `--retry-failed-pages`, the pages failed and why, to try once more
+    // once the rest are done
+    let mut failed = Vec::new();
+
+    // with `--fetch-buffer`, pages are fetched ahead of the rest, on a task of
+    // their own. those done before are skipped there just as here, to keep in
+    // step, and given as `None`
+    let mut prefetched = opts.fetch_buffer.map(|n| {
+        let (opts, prefix) = (opts.clone(), Arc::<str>::from(prefix.as_str()));
+        let (templates, state) = (templates.clone(), state.clone());
+        let pages = shuffled.clone().into_iter().chain(confirmed + 1..);
+
+        bbbsc::prefetch(pages, n, move |jdx| {
+            let (opts, prefix, state) = (opts.clone(), prefix.clone(), state.clone());
+            let templates = templates.page(jdx);
+
+            async move {
+                if let Some(s) = &state {
+                    if s.done(idx, jdx).await {
+                        return ControlFlow::Continue(None);
+                    }
+                }
+
+                let path = format!("{prefix}{jdx:04}");
+                let page = fetch_page(&opts, (idx, jdx), &path, &templates);
+                let res = with_page_timeout(&opts, page).await;
+
+                // as below, past the pages found by probing
+                match res {
+                    Ok(ControlFlow::Break(e)) if jdx > confirmed => {
+                        ControlFlow::Break(Some(Ok(ControlFlow::Break(e))))
+                    }
+                    res => ControlFlow::Continue(Some(res)),
+                }
+            }
+        })
+    });
+
+    for jdx in shuffled.into_iter().chain(confirmed + 1..) {
+        let ahead = match &mut prefetched {
+            Some(p) => match p.next().await {
+                Some(ahead) => ahead,
+                None => break,
+            },
+            None => None,
+        };
+
+        if let Some(s) = &state {
+            if s.done(idx, jdx).await {
+                summary.pages += 1;
+                summary.resumed += 1;
+                continue;
+            }
+        }
+
+        if let Err(e) = opts.ensure_free_space(&prefix) {
+            error!("{e}");
+            break;
+        }
+
+        // what's left would likely need retrying just as well
+        if opts.unhealthy() {
+            break;
+        }
+
+        if !opts.budget.start_page() {
+            break;
+        }
+
+        let path = format!("{prefix}{jdx:04}");
+        let templates = templates.page(jdx);
+
+        let page = run_page(&opts, (idx, jdx), &path, &templates, ahead, &mut pending);
+        let res = with_page_timeout(&opts, page).await;
+
+        // a page found by probing isn't the end, whatever happens to it
+        let res = match res {
+            Ok(ControlFlow::Break(e)) if jdx <= confirmed => Err(e),
+            res => res,
+        };
+
+        match res {
+            Ok(ControlFlow::Continue(())) => match &state {
+                Some(s) if !deferred => s.complete(idx, jdx).await,
+                _ => done.push(jdx),
+            },
+            Ok(ControlFlow::Break(e)) => {
+                error!("{path}: {e}");
+                pending.records.remove(&jdx);
+                opts.budget.cancel_page();
+                break;
+            }
+            // the page is left as it was, to be done once there's more space
+            Err(e) if opts.low_on_space() => {
+                error!("{path}: {e}");
+                pending.records.remove(&jdx);
+                opts.budget.cancel_page();
+                break;
+            }
+            // a page running out of time fails on its own, whatever `--lenient`
+            Err(e) if opts.lenient || matches!(e, Error::Timeout(_)) => {
+                pending.records.remove(&jdx);
+
+                if opts.retry_failed_pages {
+                    warning!("{path}: {e}, retrying once the chapter is done");
+                    failed.push((jdx, e));
+                } else {
+                    error!("{path}: {e}");
+                    fail(jdx, e);
+                }
+            }
+            Err(e) => return Err(e),
+        }
+
+        summary.pages += 1;
+    }
+
+    // nothing is fetched ahead of the retries
+    drop(prefetched);
+
+    // once, in page order, as transient failures may have cleared by now
+    failed.sort_by_key(|(jdx, ..)| *jdx);
+
+    for (jdx, e) in failed {
+        let path = format!("{prefix}{jdx:04}");
+
+        // left failed, as retrying would likely fail just as well
+        if opts.unhealthy() || opts.low_on_space() {
+            error!("{path}: {e}");
+            fail(jdx, e);
+            continue;
+        }
+
+        verbose!(opts, "{path}: retrying");
+
+        let templates = templates.page(jdx);
+        let page = run_page(&opts, (idx, jdx), &path, &templates, None, &mut pending);
+        let res = with_page_timeout(&opts, page).await;
+
+        match res {
+            Ok(ControlFlow::Continue(())) => {
+                info!("{path}: succeeded on retry");
+
+                match &state {
+                    Some(s) if !deferred => s.complete(idx, jdx).await,
+                    _ => done.push(jdx),
+                }
+            }
+            Ok(ControlFlow::Break(e)) | Err(e) => {
+                error!("{path}: {e}");
+                pending.records.remove(&jdx);
+                fail(jdx, e);
+            }
+        }
+    }
+
+    // the last page, when it has no second half
+    if let Some(h) = pending.half.take() {
+        let (jdx, path) = (h.page.1, h.path.clone());
+
+        let res = h.write(&opts, &mut pending).await;
+        unwritten |= res.is_err();
+
+        if res.is_err() {
+            pending.records.remove(&jdx);
+        }
+
+        match res {
+            Ok(()) => {}
+            Err(e) if opts.low_on_space() => error!("{path}: {e}"),
+            Err(e) if opts.lenient => {
+                error!("{path}: {e}");
+                fail(jdx, e);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    // pages handed to `--write-queue` aren't done until they're written
+    for (jdx, path, written) in pending.writes {
+        let res = written.await.unwrap_or_else(|_| Err(writer_gone()));
+        unwritten |= res.is_err();
+
+        if res.is_err() {
+            pending.records.remove(&jdx);
+        }
+
+        match res {
+            Ok(()) => {}
+            Err(e) if opts.low_on_space() => error!("{path}: {e}"),
+            Err(e) if opts.lenient => {
+                error!("{path}: {e}");
+                fail(jdx, e);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    // as which of them weren't is lost, none are recorded if any wasn't
+    if let Some(s) = state.filter(|_| !unwritten) {
+        for jdx in done {
+            s.complete(idx, jdx).await;
+        }
+    }
+
+    // those restored before there was a size to check them against, which are
+    // written already
+    if let Some(mode) = pending.sizes.mode() {
+        for (jdx, size) in &pending.sizes.unchecked {
+            if let Some(e) = Sizes::deviation(*size, mode, opts.dimension_tolerance) {
+                warning!("{prefix}{jdx:04}: {e}");
+            }
+        }
+    }
+
+    // an aid only, which doesn't fail the chapter
+    if let Some(c) = &opts.contact_sheet {
+        let out = format!("{prefix}contact-sheet.webp");
+
+        if let Err(e) = write_contact_sheet(&opts, c, &out, pending.thumbs).await {
+            error!("{out}: {e}");
+        }
+    }
+
+    if opts.cbz {
+        write_cbz(&opts, &dir, &prefix).await?;
+    }
+
+    if opts.manifest {
+        summary.written = pending
+            .records
+            .into_iter()
+            .map(|(page, r)| WrittenPage {
+                book: book.clone(),
+                chapter: idx,
+                page,
+                files: r.files,
+                timing: opts.manifest_timings.then_some(r.timing),
+            })
+            .collect();
+    }
+
+    Ok(summary)
+}
+
+/// composes `thumbs`, in the order of their pages, into `out`, replacing what
+/// an earlier run wrote there. nothing is written without any.
+async fn write_contact_sheet(
+    opts: &Options,
+    c: &ContactSheet,
+    out: &str,
+    mut thumbs: Vec<(usize, image::RgbaImage)>,
+) -> Result<()> {
+    if thumbs.is_empty() {
+        return Ok(());
+    }
+
+    thumbs.sort_by_key(|(jdx, _)| *jdx);
+    let thumbs = thumbs.into_iter().map(|(_, t)| t).collect::<Vec<_>>();
+
+    let sheet = c.compose(&thumbs);
+    let bytes = opts.encoding.encode(&sheet, opts.channels.fit(&sheet))?;
+
+    opts.ensure_free_space(out)?;
+
+    let part = format!("{out}.part");
+    tokio::fs::write(&part, bytes).await?;
+    tokio::fs::rename(&part, out).await?;
+
+    Ok(())
+}
+
+/// packs the pages in `prefix`, those written before included, into `{dir}.cbz`,
+/// or with `--archive-chunk-size`, into `{dir}-01.cbz` and on, of up to that
+/// many pages each. an archive an earlier run wrote there is appended the
+/// pages it lacks to, when it's found intact against its sidecar index
+/// `{out}.index`, and replaced otherwise.
+async fn write_cbz(opts: &Options, dir: &str, prefix: &str) -> Result<()> {
+    use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+
+    let (parent, start) = prefix.rsplit_once('/').unwrap_or((".", prefix));
+
+    let mut pages = Vec::new();
+    let mut entries = tokio::fs::read_dir(parent).await?;
+
+    while let Some(e) = entries.next_entry().await? {
+        let name = e.file_name().to_string_lossy().into_owned();
+
+        // the first views only, as views of `--all-views` are named further
+        let page = name
+            .strip_prefix(start)
+            .and_then(|r| r.strip_suffix(".webp"));
+        if page.is_some_and(|p| p.len() == 4 && p.bytes().all(|b| b.is_ascii_digit())) {
+            pages.push(name);
+        }
+    }
+    pages.sort();
+
+    let chunks = match opts.archive_chunk_size {
+        Some(n) => pages.chunks(n).collect::<Vec<_>>(),
+        None if pages.is_empty() => Vec::new(),
+        None => vec![&pages[..]],
+    };
+
+    for (k, chunk) in chunks.into_iter().enumerate() {
+        let out = match opts.archive_chunk_size {
+            Some(_) => format!("{dir}-{:02}.cbz", k + 1),
+            None => format!("{dir}.cbz"),
+        };
+        let index = format!("{out}.index");
+
+        // named as in chapter directories, whatever the layout
+        let names = chunk
+            .iter()
+            .map(|n| n[start.len()..].to_owned())
+            .collect::<Vec<_>>();
+
+        // rewritten from scratch with `--force`, as pages may have been too
+        let resumed = match opts.force {
+            true => None,
+            false => resumable_cbz(opts, &out, &index, &names).await,
+        };
+        if resumed
+            .as_ref()
+            .is_some_and(|(e, _)| e.len() == names.len())
+        {
+            continue;
+        }
+
+        opts.ensure_free_space(&out)?;
+
+        // appended to in place, or written through `{out}.part`
+        let part = format!("{out}.part");
+        let (file, mut entries, mut at) = match resumed {
+            Some((entries, at)) => {
+                let mut f = tokio::fs::OpenOptions::new().write(true).open(&out).await?;
+                f.set_len(at).await?;
+                f.seek(std::io::SeekFrom::Start(at)).await?;
+                (f, entries, at)
+            }
+            None => (tokio::fs::File::create(&part).await?, Vec::new(), 0),
+        };
+        let appending = !entries.is_empty();
+        let mut file = tokio::io::BufWriter::new(file);
+
+        for name in &names[entries.len()..] {
+            let bytes = tokio::fs::read(format!("{parent}/{start}{name}")).await?;
+            let entry = ZipEntry::new(name, &bytes, at)?;
+
+            let local = entry.local(&bytes);
+            file.write_all(&local).await?;
+            at += local.len() as u64;
+
+            entries.push(entry);
+        }
+
+        file.write_all(&zip_directory(&entries, at)?).await?;
+        file.flush().await?;
+        file.into_inner().sync_all().await?;
+
+        if !appending {
+            tokio::fs::rename(&part, &out).await?;
+        }
+
+        // only once the archive is, so that it's never ahead of it
+        let json = serde_json::to_vec(&entries)?;
+        let part = format!("{index}.part");
+        tokio::fs::write(&part, json).await?;
+        tokio::fs::rename(&part, &index).await?;
+    }
+
+    Ok(())
+}
+
+/// the entries of `out` and where its central directory starts, for it to be
+/// appended to, when its sidecar `index` lists exactly what its central
+/// directory does, and those are the first of `names`, in order. `None`
+/// otherwise, e.g. when either is missing, or an append was interrupted.
+async fn resumable_cbz(
+    opts: &Options,
+    out: &str,
+    index: &str,
+    names: &[String],
+) -> Option<(Vec<ZipEntry>, u64)> {
+    let indexed = tokio::fs::read(index).await.ok()?;
+    let indexed = match serde_json::from_slice::<Vec<ZipEntry>>(&indexed) {
+        Ok(i) => i,
+        Err(e) => {
+            warning!("{index}: {e}, rewriting {out}");
+            return None;
+        }
+    };
+
+    let (entries, at) = match read_zip_directory(out).await {
+        Ok(Some(d)) => d,
+        Ok(None) | Err(_) => {
+            warning!("{out}: isn't a complete archive, rewriting it");
+            return None;
+        }
+    };
+
+    let prefix = names.iter().take(indexed.len());
+    if entries != indexed
+        || indexed.len() > names.len()
+        || !prefix.eq(indexed.iter().map(|e| &e.name))
+    {
+        verbose!(opts, "{out}: doesn't match {index}, rewriting it");
+        return None;
+    }
+
+    Some((entries, at))
+}
+
+/// an entry of a cbz, stored as it is, since pages are compressed already.
+/// also what's recorded of it in the sidecar index.
+#[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+struct ZipEntry {
+    name: String,
+    crc: u32,
+    size: u32,
+    /// where its local header is.
+    offset: u32,
+}
+
+impl ZipEntry {
+    fn new(name: &str, bytes: &[u8], offset: u64) -> Result<Self> {
+        u16::try_from(name.len()).map_err(too_large_to_zip)?;
+
+        let mut crc = flate2::Crc::new();
+        crc.update(bytes);
+
+        Ok(Self {
+            name: name.to_owned(),
+            crc: crc.sum(),
+            size: u32::try_from(bytes.len()).map_err(too_large_to_zip)?,
+            offset: u32::try_from(offset).map_err(too_large_to_zip)?,
+        })
+    }
+
+    /// version needed, flags, stored, the time and date of 1980-01-01, crc,
+    /// sizes, the name's length and no extra field, as both headers have.
+    fn fields(&self) -> Vec<u8> {
+        let mut fields = Vec::new();
+        for v in [20u16, 0, 0, 0, 0x21] {
+            fields.extend(v.to_le_bytes());
+        }
+        for v in [self.crc, self.size, self.size] {
+            fields.extend(v.to_le_bytes());
+        }
+        fields.extend((self.name.len() as u16).to_le_bytes());
+        fields.extend(0u16.to_le_bytes());
+        fields
+    }
+
+    /// its local header, followed by `bytes`.
+    fn local(&self, bytes: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend(0x04034b50u32.to_le_bytes());
+        out.extend(self.fields());
+        out.extend(self.name.as_bytes());
+        out.extend(bytes);
+        out
+    }
+
+    /// its record in the central directory: version made by, the same fields,
+    /// no comment, disk, attributes and where the local header is.
+    fn central(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend(0x02014b50u32.to_le_bytes());
+        out.extend(20u16.to_le_bytes());
+        out.extend(self.fields());
+        out.extend([0; 10]);
+        out.extend(self.offset.to_le_bytes());
+        out.extend(self.name.as_bytes());
+        out
+    }
+}
+
+fn too_large_to_zip<E>(_: E) -> Error {
+    Error::Validation("too large to zip without zip64".to_owned())
+}
+
+/// the central directory of `entries`, starting `at`, and the end of it all.
+fn zip_directory(entries: &[ZipEntry], at: u64) -> Result<Vec<u8>> {
+    let central = entries
+        .iter()
+        .flat_map(ZipEntry::central)
+        .collect::<Vec<_>>();
+
+    let count = u16::try_from(entries.len()).map_err(too_large_to_zip)?;
+    let offset = u32::try_from(at).map_err(too_large_to_zip)?;
+    let size = u32::try_from(central.len()).map_err(too_large_to_zip)?;
+
+    let mut out = central;
+    out.extend(0x06054b50u32.to_le_bytes());
+    out.extend([0; 4]);
+    out.extend(count.to_le_bytes());
+    out.extend(count.to_le_bytes());
+    out.extend(size.to_le_bytes());
+    out.extend(offset.to_le_bytes());
+    out.extend([0; 2]);
+
+    Ok(out)
+}
+
+/// the entries in the central directory of the zip at `path` and where it
+/// starts, as written by `zip_directory`: without a comment, and ending the
+/// file. `None` when it isn't found so.
+async fn read_zip_directory(path: &str) -> Result<Option<(Vec<ZipEntry>, u64)>> {
+    use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+    let mut file = tokio::fs::File::open(path).await?;
+    let len = file.metadata().await?.len();
+    if len < 22 {
+        return Ok(None);
+    }
+
+    let u16_at = |b: &[u8], i: usize| u16::from_le_bytes([b[i], b[i + 1]]);
+    let u32_at = |b: &[u8], i: usize| u32::from_le_bytes([b[i], b[i + 1], b[i + 2], b[i + 3]]);
+
+    let mut end = [0; 22];
+    file.seek(std::io::SeekFrom::Start(len - 22)).await?;
+    file.read_exact(&mut end).await?;
+
+    let (count, size, at) = (u16_at(&end, 10), u32_at(&end, 12), u32_at(&end, 16));
+    if u32_at(&end, 0) != 0x06054b50 || u64::from(at) + u64::from(size) + 22 != len {
+        return Ok(None);
+    }
+
+    let mut central = vec![0; size as usize];
+    file.seek(std::io::SeekFrom::Start(at.into())).await?;
+    file.read_exact(&mut central).await?;
+
+    let mut entries = Vec::new();
+    let mut rest = &central[..];
+
+    for _ in 0..count {
+        if rest.len() < 46 || u32_at(rest, 0) != 0x02014b50 {
+            return Ok(None);
+        }
+
+        let name_len = u16_at(rest, 28) as usize;
+        let skip = 46 + name_len + u16_at(rest, 30) as usize + u16_at(rest, 32) as usize;
+        let Some(name) = rest.get(46..46 + name_len).filter(|_| rest.len() >= skip) else {
+            return Ok(None);
+        };
+
+        entries.push(ZipEntry {
+            name: String::from_utf8_lossy(name).into_owned(),
+            crc: u32_at(rest, 16),
+            size: u32_at(rest, 24),
+            offset: u32_at(rest, 42),
+        });
+        rest = &rest[skip..];
+    }
+
+    if !rest.is_empty() {
+        return Ok(None);
+    }
+
+    Ok(Some((entries, at.into())))
+}
+
+/// pages streamed into a tar archive with `--tar` as they're written, by
+/// chapter, then page. those of the chapter being streamed are written right
+/// away, while those of later ones are held until the chapters before are
+/// done. with `--shuffle` or `--retry-failed-pages`, a chapter's pages are
+/// held until it's done, too.
+struct Tar(tokio::sync::Mutex<TarState>);
+
+struct TarState {
+    out: Box<dyn tokio::io::AsyncWrite + Send + Unpin>,
+    hold: bool,
+    streaming: usize,
+    done: std::collections::BTreeSet<usize>,
+    held: std::collections::BTreeMap<usize, Vec<(usize, String, Vec<u8>)>>,
+}
+
+impl Tar {
+    /// streams into `path`, replacing what's there, or stdout for `-`.
+    async fn create(path: &str, hold: bool) -> std::io::Result<Self> {
+        let out: Box<dyn tokio::io::AsyncWrite + Send + Unpin> = match path {
+            "-" => Box::new(tokio::io::stdout()),
+            p => Box::new(tokio::fs::File::create(p).await?),
+        };
+
+        Ok(Self(tokio::sync::Mutex::new(TarState {
+            out: Box::new(tokio::io::BufWriter::new(out)),
+            hold,
+            streaming: 1,
+            done: Default::default(),
+            held: Default::default(),
+        })))
+    }
+
+    /// adds `bytes` as `name`, of `page`, or holds it until its turn.
+    async fn push(&self, page: (usize, usize), name: String, bytes: Vec<u8>) -> Result<()> {
+        let mut s = self.0.lock().await;
+
+        if page.0 == s.streaming && !s.hold {
+            return s.write(&name, &bytes).await;
+        }
+
+        s.held
+            .entry(page.0)
+            .or_default()
+            .push((page.1, name, bytes));
+        Ok(())
+    }
+
+    /// writes what's held of chapter `idx` and the ones after it, once those
+    /// before it are done.
+    async fn chapter_done(&self, idx: usize) -> Result<()> {
+        let mut s = self.0.lock().await;
+        s.done.insert(idx);
+
+        loop {
+            let idx = s.streaming;
+            let done = s.done.remove(&idx);
+
+            if done || !s.hold {
+                s.write_held(idx).await?;
+            }
+            if !done {
+                return Ok(());
+            }
+
+            s.streaming += 1;
+        }
+    }
+
+    /// writes whatever is still held, for the next book to start over.
+    async fn book_done(&self) -> Result<()> {
+        let mut s = self.0.lock().await;
+
+        while let Some(&idx) = s.held.keys().next() {
+            s.write_held(idx).await?;
+        }
+
+        s.streaming = 1;
+        s.done.clear();
+        Ok(())
+    }
+
+    /// ends the archive with its two empty blocks.
+    async fn finish(&self) -> Result<()> {
+        use tokio::io::AsyncWriteExt;
+
+        let mut s = self.0.lock().await;
+        s.out.write_all(&[0; 1024]).await?;
+        s.out.flush().await?;
+
+        Ok(())
+    }
+}
+
+impl TarState {
+    async fn write_held(&mut self, idx: usize) -> Result<()> {
+        let mut held = self.held.remove(&idx).unwrap_or_default();
+        // stable, for views to stay in the order they were written
+        held.sort_by_key(|(jdx, ..)| *jdx);
+
+        for (_, name, bytes) in held {
+            self.write(&name, &bytes).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn write(&mut self, name: &str, bytes: &[u8]) -> Result<()> {
+        use tokio::io::AsyncWriteExt;
+
+        let at = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+
+        self.out
+            .write_all(tar_header(name, bytes.len(), at)?.as_bytes())
+            .await?;
+        self.out.write_all(bytes).await?;
+        self.out
+            .write_all(&vec![0; bytes.len().next_multiple_of(512) - bytes.len()])
+            .await?;
+
+        // for whatever reads it to go on right away
+        self.out.flush().await?;
+
+        Ok(())
+    }
+}
+
+/// the ustar header of a regular file `name` of `size` bytes, modified `at`.
+/// names over 100 bytes are split into a prefix at a `/`.
+fn tar_header(name: &str, size: usize, at: u64) -> Result<tar::Header> {
+    let mut h = tar::Header::new_ustar();
+
+    h.set_path(name)
+        .map_err(|e| Error::Validation(format!("{name} can't be a tar entry: {e}")))?;
+    h.set_entry_type(tar::EntryType::Regular);
+    h.set_size(size as u64);
+    h.set_mode(0o644);
+    h.set_uid(0);
+    h.set_gid(0);
+    h.set_mtime(at);
+    h.set_cksum();
+
+    Ok(h)
+}
+
+/// what's in the directory of `prefix` and named starting with the rest of it,
+/// but not after a page, as `is_page_file` tells, with `--clean-output`.
+async fn unexpected_files(prefix: &str, kinds: &[Kind]) -> Result<Vec<String>> {
+    let (dir, start) = prefix.rsplit_once('/').unwrap_or((".", prefix));
+
+    let mut found = Vec::new();
+    let mut entries = tokio::fs::read_dir(dir).await?;
+
+    while let Some(e) = entries.next_entry().await? {
+        let name = e.file_name().to_string_lossy().into_owned();
+
+        if let Some(rest) = name.strip_prefix(start) {
+            if e.file_type().await?.is_dir() || !is_page_file(rest, kinds) {
+                found.push(format!("{dir}/{name}"));
+            }
+        }
+    }
+
+    found.sort();
+    Ok(found)
+}
+
+/// whether `name` is one written for a page: its number, then `.ptimg.json`,
+/// `.html`, `.jpg`, a numbered resource like `.1.png`, `.webp` or
+/// `.webp.sha256`, also after a view's name like `.thumbnail`, each possibly
+/// still a `.part` (with a `.part.validator`), or `--contact-sheet`'s.
+fn is_page_file(name: &str, kinds: &[Kind]) -> bool {
+    if name.strip_suffix(".part").unwrap_or(name) == "contact-sheet.webp" {
+        return true;
+    }
+
+    let digits = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+
+    let Some((page, rest)) = name.split_once('.') else {
+        return false;
+    };
+    if page.len() != 4 || !digits(page) {
+        return false;
+    }
+
+    let rest = rest
+        .strip_suffix(".part.validator")
+        .or_else(|| rest.strip_suffix(".part"))
+        .unwrap_or(rest);
+
+    let view = |s: &str| {
+        !s.is_empty()
+            && s.bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+    };
+
+    if kinds.iter().any(|k| k.ext == rest) {
+        return true;
+    }
+
+    match rest {
+        "ptimg.json" | "html" | "jpg" | "webp" | "webp.sha256" => true,
+        _ => match rest.split_once('.') {
+            Some((v, "webp" | "webp.sha256")) => view(v),
+            Some((n, ext)) => digits(n) && !ext.is_empty() && !ext.contains('.'),
+            None => false,
+        },
+    }
+}
+
+/// how many pages a chapter has, by fetching their ptimgs one after another
+/// until one isn't found, or fails.
+async fn probe(opts: &Options, prefix: &str, templates: &Templates) -> usize {
+    let mut n = 0;
+
+    loop {
+        let path = format!("{prefix}{:04}", n + 1);
+
+        match fetch_ptimg(opts, &path, &templates.page(n + 1)).await {
+            Ok(ControlFlow::Continue(_)) => n += 1,
+            Ok(ControlFlow::Break(_)) | Err(_) => return n,
+        }
+    }
+}
+
+/// `page`, as an error of its own once it runs longer than `--page-timeout`.
+/// it's dropped then, wherever it's at, so what it writes must hold up to that.
+async fn with_page_timeout<T>(
+    opts: &Options,
+    page: impl std::future::Future<Output = Result<T>>,
+) -> Result<T> {
+    match opts.page_timeout {
+        Some(t) => tokio::time::timeout(t, page)
+            .await
+            .unwrap_or(Err(Error::Timeout(t))),
+        None => page.await,
+    }
+}
+
+/// fetches, descrambles and writes a single page, in stages:
+///
+/// 1. fetch and parse the ptimg
+/// 2. determine the resources its coords refer to
+/// 3. fetch and decode those, concurrently
+/// 4. descramble
+/// 5. with `--stitch-spreads`, keep the first half of a spread in `pending`,
+///    or stitch the second one to it
+/// 6. encode, embedding metadata with `--embed-metadata`
+/// 7. write, or link to an identical page with `--dedupe-output`, and its
+///    checksum with `--checksums`, or queue that with `--write-queue`
+///
+/// a fetch failing in 1. or 3. breaks, as the page (and so the chapter) is
+/// over. anything else failing is an error of the page. with `--fetch-buffer`,
+/// 1. to 3. up to decoding were done ahead, as given by `ahead`.
+async fn run_page(
+    opts: &Options,
+    page: (usize, usize),
+    path: &str,
+    templates: &Templates,
+    ahead: Option<Result<ControlFlow<Error, FetchedPage>>>,
+    pending: &mut Pending,
+) -> Result<ControlFlow<Error>> {
+    let fetched = match ahead {
+        Some(fetched) => fetched?,
+        None => fetch_page(opts, page, path, templates).await?,
+    };
+    let fetched = match fetched {
+        ControlFlow::Continue(f) => f,
+        ControlFlow::Break(e) => return Ok(ControlFlow::Break(e)),
+    };
+
+    let fetching = fetched.fetching;
+    let sources = source_urls(&fetched.sources);
+    let source_bytes = fetched
+        .sources
+        .iter()
+        .map(|(_, b, _)| b.len() as u64)
+        .sum::<u64>();
+
+    let Views {
+        first: img,
+        rest,
+        decoding,
+    } = match descramble_page(opts, page, path, Some(&sources), fetched).await? {
+        ControlFlow::Continue(v) => v,
+        ControlFlow::Break(e) => return Ok(ControlFlow::Break(e)),
+    };
+
+    if let Some(check) = opts.check_dimensions {
+        let size = img.dimensions();
+
+        match pending.sizes.mode() {
+            Some(mode) => match Sizes::deviation(size, mode, opts.dimension_tolerance) {
+                Some(e) if matches!(check, DimensionCheck::Error) => {
+                    return Err(Error::Validation(e));
+                }
+                Some(e) => warning!("{path}: {e}"),
+                None => {}
+            },
+            None => pending.sizes.unchecked.push((page.1, size)),
+        }
+
+        pending.sizes.add(size);
+    }
+
+    let timing = &mut pending.records.entry(page.1).or_default().timing;
+    timing.fetch += fetching;
+    timing.decode += decoding;
+    timing.source_bytes += source_bytes;
+
+    if let Some(c) = &opts.contact_sheet {
+        pending
+            .thumbs
+            .push((page.1, c.thumbnail(&img, opts.restore.filter)));
+    }
+
+    // not part of spreads
+    for (name, bytes, encoding) in rest {
+        let path = format!("{path}.{name}");
+        write_encoded(opts, page, &path, bytes, encoding, pending).await?;
+    }
+
+    let Some(spreads) = opts.stitch_spreads else {
+        write_page(opts, page, path, &sources, &img, pending).await?;
+        return Ok(ControlFlow::Continue(()));
+    };
+
+    if spreads.starts(page.1) {
+        // one whose other half failed is left alone
+        if let Some(h) = pending.half.take() {
+            h.write(opts, pending).await?;
+        }
+
+        pending.half = Some(Half {
+            page,
+            path: path.to_owned(),
+            sources,
+            img,
+        });
+
+        return Ok(ControlFlow::Continue(()));
+    }
+
+    let Some(h) = pending.half.take() else {
+        write_page(opts, page, path, &sources, &img, pending).await?;
+        return Ok(ControlFlow::Continue(()));
+    };
+
+    let path = format!("{}-{:04}", h.path, page.1);
+    if h.img.height() != img.height() {
+        warning!(
+            "{path}: halves are {} and {} high, padding",
+            h.img.height(),
+            img.height()
+        );
+    }
+
+    let spread = match opts.right_to_left {
+        true => stitch(&img, &h.img),
+        false => stitch(&h.img, &img),
+    };
+    let mut sources = [h.sources, sources].concat();
+    sources.sort();
+    sources.dedup();
+
+    write_page(opts, h.page, &path, &sources, &spread, pending).await?;
+
+    Ok(ControlFlow::Continue(()))
+}
+
+/// stages 6. and 7. of `run_page`.
+async fn write_page(
+    opts: &Options,
+    page: (usize, usize),
+    path: &str,
+    sources: &[String],
+    img: &image::RgbaImage,
+    pending: &mut Pending,
+) -> Result<()> {
+    let started = std::time::Instant::now();
+    let bytes = encode_page(opts, page, path, sources, img)?;
+
+    write_encoded(opts, page, path, bytes, started.elapsed(), pending).await
+}
+
+/// stage 6. of `run_page`.
+fn encode_page(
+    opts: &Options,
+    page: (usize, usize),
+    path: &str,
+    sources: &[String],
+    img: &image::RgbaImage,
+) -> Result<Vec<u8>> {
+    let resized;
+    let img = match &opts.print_size {
+        Some(p) => {
+            let (width, height) = p.fit(img.dimensions());
+            resized = image::imageops::resize(img, width, height, opts.restore.filter);
+            &resized
+        }
+        None => img,
+    };
+
+    let mut channels = opts.channels.fit(img);
+    if channels != opts.channels {
+        warning!(
+            "{path}: can't be {} without loss, falling back to {channels}",
+            opts.channels
+        );
+    }
+
+    if opts.drop_opaque_alpha {
+        channels = channels.drop_opaque_alpha(img);
+    }
+
+    let encoding = opts.encoding.pick(img, channels);
+    if let Encoding::Auto { .. } = opts.encoding {
+        verbose!(opts, "{path}: {encoding}");
+    }
+
+    let mut bytes = encoding.encode(img, channels)?;
+
+    let xmp = opts.embed_metadata.then(|| {
+        let meta = Metadata {
+            sources,
+            page,
+            at: std::time::SystemTime::now(),
+            dpi: opts.print_size.as_ref().map(|p| p.dpi),
+        };
+
+        meta.xmp()
+    });
+    let icc = opts.icc.as_ref().and_then(|c| c.icc.as_deref());
+
+    if xmp.is_some() || icc.is_some() {
+        match extend_webp(&bytes, img.dimensions(), icc, xmp.as_deref()) {
+            Some(b) => bytes = b,
+            None => warning!("{path}: couldn't embed metadata, skipping"),
+        }
+    }
+
+    Ok(bytes)
+}
+
+/// stage 7. of `run_page`, of a page `encode_page` took `encoding` to encode
+/// into `bytes`: it's recorded, and written with `store` or queued to be.
+async fn write_encoded(
+    opts: &Options,
+    page: (usize, usize),
+    path: &str,
+    bytes: Vec<u8>,
+    encoding: std::time::Duration,
+    pending: &mut Pending,
+) -> Result<()> {
+    let out = format!("{path}.webp");
+
+    let record = pending.records.entry(page.1).or_default();
+    record.files.push(out.clone());
+    record.timing.encode += encoding;
+    record.timing.output_bytes += bytes.len() as u64;
+
+    if let Some(t) = &opts.tar {
+        let name = out.trim_start_matches("./").to_owned();
+        t.push(page, name, bytes.clone()).await?;
+    }
+
+    match &opts.write_queue {
+        Some(q) => {
+            let done = q.push(out, bytes).await?;
+            pending.writes.push((page.1, path.to_owned(), done));
+        }
+        None => store(opts, &out, &bytes).await?,
+    }
+
+    Ok(())
+}
+
+/// stage 7. of `run_page`.
+async fn store(opts: &Options, out: &str, bytes: &[u8]) -> Result<()> {
+    opts.ensure_free_space(out)?;
+
+    // replaced rather than refused
+    if opts.force {
+        match tokio::fs::remove_file(out).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e)?,
+            _ => {}
+        }
+    }
+
+    match &opts.dedupe_output {
+        Some(d) => d.write_new(out, bytes).await?,
+        None => bbbsc::write_new_atomically(out, bytes).await?,
+    }
+
+    if opts.checksums {
+        write_checksum(out, bytes).await?;
+    }
+
+    Ok(())
+}
+
+/// what's left of a chapter once its pages ran: the first half of a spread,
+/// waiting for the second with `--stitch-spreads`, pages waiting to be written
+/// with `--write-queue`, thumbnails for `--contact-sheet`, what's recorded for
+/// `--manifest`, by page, and the sizes of pages for `--check-dimensions`.
+#[derive(Default)]
+struct Pending {
+    half: Option<Half>,
+    writes: Vec<(usize, String, tokio::sync::oneshot::Receiver<Result<()>>)>,
+    thumbs: Vec<(usize, image::RgbaImage)>,
+    records: std::collections::BTreeMap<usize, Record>,
+    sizes: Sizes,
+}
+
+/// pages waiting for `writer` to write them, with `--write-queue`.
+struct WriteQueue(tokio::sync::mpsc::Sender<Write>);
+
+struct Write {
+    out: String,
+    bytes: Vec<u8>,
+    done: tokio::sync::oneshot::Sender<Result<()>>,
+}
+
+impl WriteQueue {
+    /// queues `bytes` to be written to `out`, waiting while the queue is full,
+    /// for what's received once it's written.
+    async fn push(
+        &self,
+        out: String,
+        bytes: Vec<u8>,
+    ) -> Result<tokio::sync::oneshot::Receiver<Result<()>>> {
+        let (done, written) = tokio::sync::oneshot::channel();
+
+        let write = Write { out, bytes, done };
+        self.0.send(write).await.map_err(|_| writer_gone())?;
+
+        Ok(written)
+    }
+}
+
+/// writes the pages `queue` receives, one after another.
+async fn writer(opts: Arc<Options>, mut queue: tokio::sync::mpsc::Receiver<Write>) {
+    while let Some(w) = queue.recv().await {
+        let _ = w.done.send(store(&opts, &w.out, &w.bytes).await);
+    }
+}
+
+fn writer_gone() -> Error {
+    std::io::Error::other("the writer is gone").into()
+}
+
+/// the first half of a spread, waiting for the second with `--stitch-spreads`.
+struct Half {
+    page: (usize, usize),
+    path: String,
+    sources: Vec<String>,
+    img: image::RgbaImage,
+}
+
+impl Half {
+    /// writes it as a page on its own, for want of the second half.
+    async fn write(self, opts: &Options, pending: &mut Pending) -> Result<()> {
+        write_page(
+            opts,
+            self.page,
+            &self.path,
+            &self.sources,
+            &self.img,
+            pending,
+        )
+        .await
+    }
+}
+
+/// the urls `sources` were fetched from, for `--embed-metadata`: without
+/// credentials given as userinfo, and without inline data, nor `data:` urls,
+/// which aren't where anything came from.
+fn source_urls(sources: &Fetched) -> Vec<String> {
+    let mut urls = sources
+        .iter()
+        .filter(|(s, ..)| s.data.is_none())
+        .map(|(s, ..)| &s.url)
+        .filter(|u| !u.starts_with("data:"))
+        .map(|u| match reqwest::Url::parse(u) {
+            Ok(mut url) => {
+                let _ = url.set_username("");
+                let _ = url.set_password(None);
+                url.into()
+            }
+            Err(_) => u.clone(),
+        })
+        .collect::<Vec<_>>();
+
+    // as they're fetched in no particular order
+    urls.sort();
+    urls.dedup();
+    urls
+}
+
+/// `left` and `right` side by side, the lower one centered vertically.
+fn stitch(left: &image::RgbaImage, right: &image::RgbaImage) -> image::RgbaImage {
+    let height = left.height().max(right.height());
+    let mut out = image::RgbaImage::new(left.width() + right.width(), height);
+
+    let top = |img: &image::RgbaImage| i64::from((height - img.height()) / 2);
+    image::imageops::replace(&mut out, left, 0, top(left));
+    image::imageops::replace(&mut out, right, i64::from(left.width()), top(right));
+
+    out
+}
+
+/// where chapter `idx` goes in `path`, after `--chapter-dir`. `{title}` is
+/// looked up only when used, and falls back to the index.
+async fn chapter_dir(
+    opts: &Options,
+    path: &str,
+    idx: usize,
+    templates: &Templates,
+) -> Result<String> {
+    let index = format!("{idx:02}");
+
+    let title = if opts.chapter_dir.contains("{title}") {
+        chapter_title(opts, templates).await
+    } else {
+        None
+    };
+
+    let dir = opts
+        .chapter_dir
+        .replace("{index}", &index)
+        .replace("{title}", title.as_deref().unwrap_or(&index));
+
+    opts.sanitize.apply(&format!("{path}/{dir}"))
+}
+
+/// the title of a chapter, from `--title-url` or else the ptimg of its first
+/// page. made a single path component, whatever `--sanitize` says.
+async fn chapter_title(opts: &Options, templates: &Templates) -> Option<String> {
+    #[derive(serde::Deserialize)]
+    struct Sidecar {
+        title: Option<String>,
+    }
+
+    let res = match &templates.title {
+        Some(url) => fetch(opts, url, None).await.and_then(|f| match f {
+            ControlFlow::Continue(b) => Ok(serde_json::from_slice::<Sidecar>(&b)?.title),
+            ControlFlow::Break(e) => Err(e),
+        }),
+        None => {
+            let templates = templates.page(1);
+            let url = match &templates.ptimg {
+                Some(p) => p.clone(),
+                None => templates.target.replacen("{}", "ptimg.json", 1),
+            };
+
+            fetch(opts, &url, None).await.and_then(|f| match f {
+                ControlFlow::Continue(b) => Ok(Ptimg::from_slice(&b)?.title),
+                ControlFlow::Break(e) => Err(e),
+            })
+        }
+    };
+
+    let title = match res {
+        Ok(t) => t?,
+        Err(e) => {
+            verbose!(opts, "no title found: {e}");
+            return None;
+        }
+    };
+
+    let title = title
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | '\0'..='\x1f' => '_',
+            c => c,
+        })
+        .collect::<String>();
+
+    match title.trim() {
+        "" | "." | ".." => None,
+        t => Some(t.to_owned()),
+    }
+}
+
+/// a page as fetched, for `descramble_page` to decode and restore.
+struct FetchedPage {
+    pt: Ptimg,
+    resources: std::collections::HashMap<String, Arc<Source>>,
+    sources: Fetched,
+    /// how long fetching took.
+    fetching: std::time::Duration,
+}
+
+/// stages 1. to 3. of `run_page`, up to decoding.
+async fn fetch_page(
+    opts: &Options,
+    page: (usize, usize),
+    path: &str,
+    templates: &Templates,
+) -> Result<ControlFlow<Error, FetchedPage>> {
+    let started = std::time::Instant::now();
+    let over = opts.coords_override.get(&page).cloned();
+
+    let pt = match (fetch_ptimg(opts, path, templates).await, over) {
+        (Ok(ControlFlow::Continue(b)), over) => {
+            let pt = Ptimg::from_slice(&b)?;
+
+            match over {
+                Some(o) => o.apply(pt)?,
+                None => pt,
+            }
+        }
+        (Ok(ControlFlow::Break(_)) | Err(_), Some(Override::View(view))) => standalone(view),
+        (Ok(ControlFlow::Break(e)) | Err(e), _) => return Ok(ControlFlow::Break(e)),
+    };
+
+    let resources = resources(
+        &pt,
+        path,
+        &templates.target,
+        &opts.kinds,
+        opts.normalized_coords,
+    )?;
+
+    let sources = match fetch_sources(opts, &resources).await? {
+        ControlFlow::Continue(sources) => sources,
+        ControlFlow::Break(e) => return Ok(ControlFlow::Break(e)),
+    };
+
+    Ok(ControlFlow::Continue(FetchedPage {
+        pt,
+        resources,
+        sources,
+        fetching: started.elapsed(),
+    }))
+}
+
+/// the rest of stage 3. and stage 4. of `run_page`, and with `--all-views`,
+/// stage 6. of the views but the first, embedding `urls`. they're encoded
+/// one after another as they're restored into the same buffer, unless
+/// `urls` is `None`, as only the first is wanted.
+async fn descramble_page(
+    opts: &Options,
+    page: (usize, usize),
+    path: &str,
+    urls: Option<&[String]>,
+    fetched: FetchedPage,
+) -> Result<ControlFlow<Error, Views>> {
+    let FetchedPage {
+        mut pt,
+        resources,
+        sources,
+        ..
+    } = fetched;
+
+    // the decoded sources count towards `--max-decode-memory` until restored
+    let started = std::time::Instant::now();
+    let (images, _memory) = match decode_sources(opts, &pt, &resources, sources).await? {
+        ControlFlow::Continue(decoded) => decoded,
+        ControlFlow::Break(e) => return Ok(ControlFlow::Break(e)),
+    };
+    let decoding = started.elapsed();
+    let map = |key: &str| &images[&resources[key]];
+
+    if opts.verify_pairing {
+        let problems = pairing_problems(&pt, &resources, &images);
+
+        if !problems.is_empty() && !opts.lenient {
+            return Err(Error::Validation(format!(
+                "ptimg doesn't pair with its images: {}",
+                problems.join(", ")
+            )));
+        }
+
+        for p in problems {
+            warning!("{path}: {p}");
+        }
+    }
+
+    if opts.normalized_coords {
+        pt.denormalize(map)?;
+    }
+
+    if let Some(dir) = &opts.dump_tiles {
+        if opts.debug_pages.contains(page) {
+            dump_tiles(&format!("{dir}/{:02}/{:04}", page.0, page.1), &pt, map).await?;
+        }
+    }
+
+    if let Some(dir) = &opts.export_coords {
+        let path = format!("{dir}/{:02}/{:04}.json", page.0, page.1);
+        export_coords(&path, page, &pt).await?;
+    }
+
+    if pt.views.is_empty() {
+        return Err(Error::Validation("ptimg has no views".to_owned()));
+    }
+
+    let mut first = None;
+    let mut rest = Vec::new();
+    let mut views = pt.views.iter();
+    let mut names = bbbsc::view_names(&pt.views).into_iter();
+
+    pt.restore_each_with(map, &opts.restore, |img| {
+        let v = views.next().expect("a view for each restored");
+
+        // restoring is meant to give exactly the declared size, anything else
+        // is a bug of it
+        let declared = (v.width, v.height);
+        if img.dimensions() != declared {
+            let e = format!(
+                "restored {}x{}, but the view declares {}x{}",
+                img.width(),
+                img.height(),
+                declared.0,
+                declared.1,
+            );
+
+            if !opts.lenient {
+                return Err(Error::Validation(e));
+            }
+
+            warning!("{path}: {e}");
+        }
+
+        if first.is_none() {
+            first = Some(img.clone());
+            return Ok(());
+        }
+
+        let name = names.next().expect("a name for each view but the first");
+
+        if let (true, Some(urls)) = (opts.all_views, urls) {
+            let started = std::time::Instant::now();
+            let bytes = encode_page(opts, page, &format!("{path}.{name}"), urls, img)?;
+            rest.push((name, bytes, started.elapsed()));
+        }
+
+        Ok(())
+    })?;
+
+    let first = first.expect("a first view");
+
+    if let Some(dir) = &opts.debug_visualize {
+        if opts.debug_pages.contains(page) {
+            let path = format!("{dir}/{:02}/{:04}.png", page.0, page.1);
+            visualize(&path, &pt, map, &first).await?;
+        }
+    }
+
+    Ok(ControlFlow::Continue(Views {
+        first,
+        rest,
+        decoding,
+    }))
+}
+
+/// the views of a page, as restored.
+struct Views {
+    first: image::RgbaImage,
+    /// the others, by `view_names`, with `--all-views`, already encoded, with
+    /// how long that took.
+    rest: Vec<(String, Vec<u8>, std::time::Duration)>,
+    /// how long decoding the sources took, waiting for `--max-decode-memory`
+    /// included.
+    decoding: std::time::Duration,
+}
+
+/// descrambles `baseline.page` of the book `dist` afresh, returning the mean
+/// absolute difference of its channels from the baseline, in `0.0..=255.0`.
+async fn check_baseline(
+    opts: &Options,
+    templates: Templates,
+    dist: &str,
+    baseline: &Baseline,
+) -> Result<f64> {
+    let (idx, jdx) = baseline.page;
+
+    let path = opts
+        .sanitize
+        .apply(&format!("./{dist}/{idx:02}/{jdx:04}"))?;
+    let templates = templates.substitute(dist).chapter(idx).page(jdx);
+
+    let fetched = match fetch_page(opts, baseline.page, &path, &templates).await? {
+        ControlFlow::Continue(f) => f,
+        ControlFlow::Break(e) => return Err(e),
+    };
+    let img = match descramble_page(opts, baseline.page, &path, None, fetched).await? {
+        ControlFlow::Continue(v) => v.first,
+        ControlFlow::Break(e) => return Err(e),
+    };
+
+    let expected = image::open(&baseline.path)
+        .map_err(Error::Decode)?
+        .to_rgba8();
+    if img.dimensions() != expected.dimensions() {
+        return Ok(255.0);
+    }
+
+    let sum = img
+        .as_raw()
+        .iter()
+        .zip(expected.as_raw())
+        .map(|(a, b)| a.abs_diff(*b) as u64)
+        .sum::<u64>();
+
+    Ok(sum as f64 / img.as_raw().len().max(1) as f64)
+}
+
+/// fetches the ptimg of a page: from `--ptimg` when given, next to the source
+/// image otherwise. with `--html`, it's looked up in the page's html first,
+/// falling back to the above when it isn't found there.
+async fn fetch_ptimg(
+    opts: &Options,
+    path: &str,
+    templates: &Templates,
+) -> Result<ControlFlow<Error, Vec<u8>>> {
+    let fallback = match &templates.ptimg {
+        Some(p) => p.clone(),
+        None => templates.target.replacen("{}", "ptimg.json", 1),
+    };
+
+    let url =
+        match &templates.html {
+            Some(html) => {
+                let bytes =
+                    match try_use_cache_otherwise_fetch(opts, &format!("{path}.html"), html, None)
+                        .await
+                    {
+                        Ok(ControlFlow::Continue(b)) => b,
+                        Ok(ControlFlow::Break(e)) | Err(e) => return Ok(ControlFlow::Break(e)),
+                    };
+
+                match opts
+                    .html_extract
+                    .extract(&String::from_utf8_lossy(&bytes), html)
+                {
+                    Some(Extracted::Json(json)) => {
+                        return Ok(ControlFlow::Continue(json.into_bytes()))
+                    }
+                    Some(Extracted::Url(url)) => url,
+                    None => {
+                        warning!("{path}: no ptimg found in its html, falling back");
+                        fallback
+                    }
+                }
+            }
+            None => fallback,
+        };
+
+    try_use_cache_otherwise_fetch(opts, &format!("{path}.ptimg.json"), &url, None).await
+}
+
+/// where a resource is cached and fetched from.
+#[derive(PartialEq, Eq, Hash)]
+struct Source {
+    path: String,
+    url: String,
+    /// the bytes of inline `data`, taken as they are rather than fetched.
+    data: Option<Vec<u8>>,
+}
+
+/// the resource key `coord` refers to, in fractions when `normalized`.
+fn coord_key(coord: &impl AsRef<str>, normalized: bool) -> Result<&str> {
+    match normalized {
+        true => bbbsc::parse_normalized_coord(coord).map(|(k, _)| k),
+        false => bbbsc::parse_coord(coord).map(|(k, _)| k),
+    }
+}
+
+/// what tells the ptimg of a page wasn't meant for the images it's paired with,
+/// for `--verify-pairing`: a resource declaring another file name in its `src`
+/// than that of the image it's taken from, or another size than that's of.
+fn pairing_problems(
+    pt: &Ptimg,
+    resources: &std::collections::HashMap<String, Arc<Source>>,
+    images: &Decoded,
+) -> Vec<String> {
+    let name = |s: &str| {
+        let s = s.split(['?', '#']).next().unwrap_or(s);
+        s.rsplit('/').next().unwrap_or(s).to_owned()
+    };
+
+    let mut keys = resources.keys().collect::<Vec<_>>();
+    keys.sort();
+
+    let mut problems = Vec::new();
+    for key in keys {
+        let (Some(r), src) = (pt.resources.get(key), &resources[key]) else {
+            continue;
+        };
+        // inline data has no file nor url to mistake
+        if r.data.is_some() {
+            continue;
+        }
+
+        if !r.src.is_empty() && name(&r.src) != name(&src.url) {
+            problems.push(format!(
+                "`{key}` is of `{}`, but was fetched from {}",
+                r.src, src.url
+            ));
+        }
+
+        let img = &images[src];
+        if r.width > 0 && r.height > 0 && !r.matches(img) {
+            problems.push(format!(
+                "`{key}` is of {}x{}, but {} is {}x{}",
+                r.width,
+                r.height,
+                src.url,
+                img.width(),
+                img.height()
+            ));
+        }
+    }
+
+    problems
+}
+
+/// maps every resource key the coords of `pt` refer to onto its source. one
+/// with inline `data` is that, decoded, which is never cached. when
+/// the others declare a single `src` between them, or none, that's the page's
+/// own image, from `target`. otherwise, each declared `src` is resolved against
+/// the url of that image, and cached next to it by its position among them;
+/// keys not declared are still the page's own image.
+fn resources(
+    pt: &Ptimg,
+    path: &str,
+    target: &str,
+    kinds: &[Kind],
+    normalized: bool,
+) -> Result<std::collections::HashMap<String, Arc<Source>>> {
+    let page = Arc::new(Source {
+        path: format!("{path}.jpg"),
+        url: target.replacen("{}", "jpg", 1),
+        data: None,
+    });
+
+    let mut keys = std::collections::BTreeSet::new();
+    for coord in pt.views.iter().flat_map(|v| &v.coords) {
+        keys.insert(coord_key(coord, normalized)?);
+    }
+
+    let srcs = keys
+        .iter()
+        .filter_map(|k| pt.resources.get(*k))
+        .filter(|r| r.data.is_none())
+        .map(|r| r.src.as_str())
+        .filter(|s| !s.is_empty())
+        .collect::<std::collections::BTreeSet<_>>();
+
+    let mut sources = std::collections::HashMap::new();
+    if srcs.len() > 1 {
+        for (n, src) in srcs.into_iter().enumerate() {
+            let url = reqwest::Url::parse(&page.url)
+                .and_then(|u| u.join(src))
+                .map_err(|e| {
+                    Error::Validation(format!(
+                        "couldn't resolve `{src}` against {}: {e}",
+                        page.url
+                    ))
+                })?;
+
+            let ext = std::path::Path::new(url.path())
+                .extension()
+                .and_then(|e| e.to_str())
+                .unwrap_or("jpg");
+
+            let source = Source {
+                path: format!("{path}.{}.{ext}", n + 1),
+                url: url.into(),
+                data: None,
+            };
+            sources.insert(src, Arc::new(source));
+        }
+    }
+
+    let mut resources = std::collections::HashMap::new();
+
+    // every page's kinds are fetched, whether its coords refer to them or not
+    for kind in kinds {
+        let inline = pt
+            .resources
+            .get(&kind.key)
+            .is_some_and(|r| r.data.is_some());
+
+        if !inline {
+            let source = Source {
+                path: format!("{path}.{}", kind.ext),
+                url: target.replacen("{}", &kind.ext, 1),
+                data: None,
+            };
+            resources.insert(kind.key.clone(), Arc::new(source));
+        }
+    }
+
+    for key in keys {
+        if resources.contains_key(key) {
+            continue;
+        }
+
+        let src = match pt.resources.get(key) {
+            Some(r @ bbbsc::Resource { data: Some(_), .. }) => Arc::new(Source {
+                path: format!("{path}.{key}"),
+                url: format!("inline data of `{key}`"),
+                data: r.inline().transpose()?,
+            }),
+            Some(r) => sources.get(r.src.as_str()).unwrap_or(&page).clone(),
+            None => page.clone(),
+        };
+
+        resources.insert(key.to_owned(), src);
+    }
+
+    Ok(resources)
+}
+
+/// decodes the source image `bytes` fetched from `url`, converted with `--icc`.
+/// one whose profile can't be converted from is left as it is.
+fn decode_source(opts: &Options, url: &str, bytes: &[u8]) -> Result<image::DynamicImage> {
+    let Some(c) = &opts.icc else {
+        return bbbsc::decode(bytes, opts.max_image_pixels);
+    };
+
+    let (img, icc) = bbbsc::decode_with_profile(bytes, opts.max_image_pixels)?;
+
+    match c.convert(&img, icc.as_deref()) {
+        Ok(Some(converted)) => Ok(converted),
+        Ok(None) => Ok(img),
+        Err(e) => {
+            warning!("{url}: {e}, leaving its colors as they are");
+            Ok(img)
+        }
+    }
+}
+
+/// fetches and decodes every distinct source of `resources`, up to
+/// `--resource-concurrency` at once, breaking when any of them couldn't be
+/// fetched. the rest failing are reported together, as a single error.
+type Decoded = std::collections::HashMap<Arc<Source>, image::DynamicImage>;
+
+/// the size of an image once decoded, as its header tells, or `0` if it
+/// doesn't, leaving it to decoding to fail.
+fn decoded_size(bytes: &[u8]) -> u64 {
+    use image::ImageDecoder;
+
+    image::ImageReader::new(std::io::Cursor::new(bytes))
+        .with_guessed_format()
+        .ok()
+        .and_then(|r| r.into_decoder().ok())
+        .map_or(0, |d| d.total_bytes())
+}
+
+/// what's fetched of each source, with whether it was cached.
+type Fetched = Vec<(Arc<Source>, Vec<u8>, bool)>;
+
+/// the first half of stage 3. of `run_page`.
+async fn fetch_sources(
+    opts: &Options,
+    resources: &std::collections::HashMap<String, Arc<Source>>,
+) -> Result<ControlFlow<Error, Fetched>> {
+    let sources = resources
+        .values()
+        .cloned()
+        .collect::<std::collections::HashSet<_>>();
+
+    use futures_util::StreamExt;
+
+    let fetched = futures_util::stream::iter(sources).map(|src| async {
+        let res = match &src.data {
+            Some(b) => Ok(ControlFlow::Continue((b.clone(), false))),
+            None => {
+                try_use_cache_otherwise_fetch_telling(
+                    opts,
+                    &bbbsc::FsCache,
+                    &src.path,
+                    &src.url,
+                    opts.accept.as_ref(),
+                )
+                .await
+            }
+        };
+        (src, res)
+    });
+    let fetched = fetched
+        .buffer_unordered(opts.resource_concurrency)
+        .collect::<Vec<_>>()
+        .await;
+
+    let mut bytes = Vec::new();
+    for (src, res) in fetched {
+        match res {
+            Ok(ControlFlow::Continue((b, cached))) => bytes.push((src, b, cached)),
+            Ok(ControlFlow::Break(e)) | Err(e) => return Ok(ControlFlow::Break(e)),
+        }
+    }
+
+    Ok(ControlFlow::Continue(bytes))
+}
+
+/// the second half of stage 3. of `run_page`, decoding what `fetch_sources`
+/// fetched.
+async fn decode_sources(
+    opts: &Options,
+    pt: &Ptimg,
+    resources: &std::collections::HashMap<String, Arc<Source>>,
+    bytes: Fetched,
+) -> Result<ControlFlow<Error, (Decoded, Option<tokio::sync::OwnedSemaphorePermit>)>> {
+    // the size of each source as declared by the first key of it declaring any
+    let mut declared = std::collections::HashMap::new();
+    let mut keys = resources.keys().collect::<Vec<_>>();
+    keys.sort();
+    for key in keys {
+        if let Some(r) = pt
+            .resources
+            .get(key)
+            .filter(|r| r.width > 0 && r.height > 0)
+        {
+            declared.entry(resources[key].clone()).or_insert(r);
+        }
+    }
+
+    use futures_util::StreamExt;
+
+    // what a page decodes is reserved for all at once, so that no page waits
+    // for memory while holding some of it
+    let memory = match &opts.max_decode_memory {
+        Some(m) => {
+            let size = bytes.iter().map(|(_, b, _)| decoded_size(b)).sum();
+            Some(m.reserve(opts, size).await)
+        }
+        None => None,
+    };
+
+    let declared = &declared;
+    let decoded = futures_util::stream::iter(bytes).map(|(src, b, cached)| async move {
+        let (reason, fallback) = match decode_source(opts, &src.url, &b) {
+            Ok(img) => match declared.get(&src) {
+                Some(r) if cached && !r.matches(&img) => {
+                    let reason = format!(
+                        "cached {} is {}x{}, not {}x{} as declared",
+                        src.url,
+                        img.width(),
+                        img.height(),
+                        r.width,
+                        r.height,
+                    );
+
+                    if !opts.refetch_on_size_mismatch {
+                        warning!("{reason}");
+                        return Ok(ControlFlow::Continue((src, img)));
+                    }
+
+                    (reason, Ok(img))
+                }
+                _ => return Ok::<_, Error>(ControlFlow::Continue((src, img))),
+            },
+            // what's undecodable straight from upstream is reported as is
+            Err(e) if !cached => return Err(e),
+            Err(e) => (format!("couldn't decode cached {}", src.url), Err(e)),
+        };
 
-    take_arg!(target from args);
-    take_arg!(dist   from args);
+        // a corrupted, truncated or, with `--refetch-on-size-mismatch`,
+        // differently sized cache entry is dropped and fetched once more
+        if !invalidate_cache(opts, &bbbsc::FsCache, &src.path, &src.url).await? {
+            return fallback.map(|img| ControlFlow::Continue((src, img)));
+        }
 
-    let path = format!("./{dist}");
-    let target = target.replacen("{}", &dist, 1);
+        // past `--max-total-retries`, the entry is left to the next run
+        if !opts.retry() {
+            warning!("{reason}, but no more retries are left");
+            return fallback.map(|img| ControlFlow::Continue((src, img)));
+        }
 
-    for idx in 1.. {
-        let path = format!("{path}/{idx:02}");
-        let target = target.replacen("{}", &format!("{idx:02}"), 1);
+        info!("{reason}, fetching it again");
 
-        tokio::fs::create_dir_all(&target).await.unwrap();
+        match try_use_cache_otherwise_fetch(opts, &src.path, &src.url, opts.accept.as_ref()).await {
+            Ok(ControlFlow::Continue(b)) => {
+                let img = decode_source(opts, &src.url, &b)?;
+                Ok(ControlFlow::Continue((src, img)))
+            }
+            Ok(ControlFlow::Break(e)) | Err(e) => Ok(ControlFlow::Break(e)),
+        }
+    });
+    let decoded = decoded
+        .buffer_unordered(opts.resource_concurrency)
+        .collect::<Vec<_>>()
+        .await;
 
-        for jdx in 1.. {
-            let path = format!("{path}/{jdx:04}");
-            let target = target.replacen("{}", &format!("{jdx:04}"), 1);
+    let mut images = std::collections::HashMap::new();
+    let mut errors = Vec::new();
 
-            let ptimg = match try_use_cache_otherwise_fetch(
-                &format!("{path}.ptimg.json"),
-                &target.replacen("{}", "ptimg.json", 1),
-            )
-            .await
-            {
-                Ok(ControlFlow::Continue(b)) => b,
-                Ok(ControlFlow::Break(e)) | Err(e) => {
-                    eprintln!("error reported: {e}");
-                    break;
-                }
-            };
+    for f in decoded {
+        match f {
+            Ok(ControlFlow::Continue((src, img))) => {
+                images.insert(src, img);
+            }
+            Ok(ControlFlow::Break(e)) => return Ok(ControlFlow::Break(e)),
+            Err(e) => errors.push(e),
+        }
+    }
 
-            let rdimg = match try_use_cache_otherwise_fetch(
-                &format!("{path}.jpg"),
-                &target.replacen("{}", "jpg", 1),
-            )
-            .await
-            {
-                Ok(ControlFlow::Continue(b)) => b,
-                Ok(ControlFlow::Break(e)) | Err(e) => {
-                    eprintln!("error reported: {e}");
-                    break;
-                }
-            };
+    match errors.len() {
+        0 => Ok(ControlFlow::Continue((images, memory))),
+        1 => Err(errors.remove(0)),
+        _ => Err(Error::Resources(errors)),
+    }
+}
+
+/// writes the coords of `pt` parsed, in pixels, into `path` as json, along with
+/// the sizes of its views and resources.
+async fn export_coords(path: &str, (chapter, page): (usize, usize), pt: &Ptimg) -> Result<()> {
+    #[derive(serde::Serialize)]
+    struct Export<'a> {
+        chapter: usize,
+        page: usize,
+        resources: std::collections::BTreeMap<&'a str, Resource<'a>>,
+        views: Vec<View<'a>>,
+    }
+
+    #[derive(serde::Serialize)]
+    struct Resource<'a> {
+        src: &'a str,
+        width: usize,
+        height: usize,
+    }
 
-            let ogimg = {
-                let src = image::load_from_memory(&rdimg).unwrap();
+    #[derive(serde::Serialize)]
+    struct View<'a> {
+        width: u32,
+        height: u32,
+        label: Option<&'a str>,
+        tiles: Vec<Tile<'a>>,
+    }
+
+    #[derive(serde::Serialize)]
+    struct Tile<'a> {
+        key: &'a str,
+        #[serde(flatten)]
+        replacer: bbbsc::Replacer,
+        #[serde(rename = "z-index")]
+        z_index: i64,
+    }
 
-                let pt = serde_json::from_slice::<Ptimg>(&ptimg).unwrap();
-                pt.restore(|_| &src).remove(0)
+    let resources = pt
+        .resources
+        .iter()
+        .map(|(k, r)| {
+            let r = Resource {
+                src: &r.src,
+                width: r.width,
+                height: r.height,
             };
+            (k.as_str(), r)
+        })
+        .collect();
+
+    let mut views = Vec::new();
+    for v in &pt.views {
+        let mut tiles = Vec::new();
+
+        for (i, coord) in v.coords.iter().enumerate() {
+            let (key, replacer) = bbbsc::parse_coord(coord)?;
+            let z_index = v.z_index.get(i).copied().unwrap_or(0);
+
+            tiles.push(Tile {
+                key,
+                replacer,
+                z_index,
+            });
+        }
+
+        views.push(View {
+            width: v.width,
+            height: v.height,
+            label: v.label.as_deref(),
+            tiles,
+        });
+    }
+
+    let export = Export {
+        chapter,
+        page,
+        resources,
+        views,
+    };
+
+    if let Some(dir) = std::path::Path::new(path).parent() {
+        bbbsc::create_dir_all(dir).await?;
+    }
+    tokio::fs::write(path, serde_json::to_vec_pretty(&export)?).await?;
+
+    Ok(())
+}
+
+/// writes every tile the coords of `pt` crop out of the resources `map` gives as
+/// `{n:03}.png` into `dir`, along with `tiles.json` telling where each was taken
+/// from and goes to.
+async fn dump_tiles<'a>(
+    dir: &str,
+    pt: &Ptimg,
+    map: impl Fn(&str) -> &'a image::DynamicImage,
+) -> Result<()> {
+    #[derive(serde::Serialize)]
+    struct Tile<'a> {
+        file: String,
+        view: usize,
+        key: &'a str,
+        #[serde(flatten)]
+        replacer: bbbsc::Replacer,
+    }
+
+    bbbsc::create_dir_all(dir).await?;
+
+    let mut tiles = Vec::new();
+
+    for (view, v) in pt.views.iter().enumerate() {
+        for coord in &v.coords {
+            let (key, replacer) = bbbsc::parse_coord(coord)?;
+            let file = format!("{:03}.png", tiles.len());
 
-            ogimg
+            let mut bytes = Vec::new();
+            replacer
+                .crop(map(key))
+                .to_image()
                 .write_to(
-                    &mut tokio::fs::OpenOptions::new()
-                        .create_new(true)
-                        .write(true)
-                        .open(format!("{path}.webp"))
-                        .await
-                        .unwrap()
-                        .try_into_std()
-                        .unwrap(),
-                    image::ImageFormat::WebP,
+                    &mut std::io::Cursor::new(&mut bytes),
+                    image::ImageFormat::Png,
                 )
-                .unwrap();
+                .map_err(Error::Encode)?;
+            tokio::fs::write(format!("{dir}/{file}"), bytes).await?;
+
+            tiles.push(Tile {
+                file,
+                view,
+                key,
+                replacer,
+            });
         }
     }
+
+    tokio::fs::write(
+        format!("{dir}/tiles.json"),
+        serde_json::to_vec_pretty(&tiles)?,
+    )
+    .await?;
+
+    Ok(())
 }
 
-fn parse_args() -> std::collections::HashMap<String, String> {
-    let mut args = std::collections::HashMap::new();
+/// writes the resources of the first view of `pt`, stacked in the order its
+/// coords first refer to them, next to `img` descrambled from them, as a png at
+/// `path`. tiles put somewhere else than where they're taken from are outlined
+/// on `img`.
+async fn visualize<'a>(
+    path: &str,
+    pt: &Ptimg,
+    map: impl Fn(&str) -> &'a image::DynamicImage,
+    img: &image::RgbaImage,
+) -> Result<()> {
+    const GAP: u32 = 8;
+    const MOVED: image::Rgba<u8> = image::Rgba([255, 0, 0, 255]);
 
-    let None = std::env::args().fold(None, |state, arg| match state {
-        None => {
-            if let Some(ident) = arg.strip_prefix("--") {
-                Some(ident.to_owned())
-            } else {
-                eprintln!("unrecognized arguments: {arg}");
-                None
+    let Some(view) = pt.views.first() else {
+        return Ok(());
+    };
+
+    let mut keys = Vec::new();
+    let mut moved = Vec::new();
+
+    for coord in &view.coords {
+        let (key, replacer) = bbbsc::parse_coord(coord)?;
+
+        if !keys.contains(&key) {
+            keys.push(key);
+        }
+
+        let src = (i64::from(replacer.src.x), i64::from(replacer.src.y));
+        if src != (replacer.dst.x, replacer.dst.y) {
+            moved.push(replacer);
+        }
+    }
+
+    let sources = keys.into_iter().map(map).collect::<Vec<_>>();
+    let left = sources.iter().map(|s| s.width()).max().unwrap_or(0);
+    let height = sources.iter().map(|s| s.height() + GAP).sum::<u32>();
+    let height = height.saturating_sub(GAP).max(img.height());
+
+    let mut out = image::RgbaImage::new(left + GAP + img.width(), height);
+
+    let mut y = 0;
+    for s in sources {
+        image::imageops::replace(&mut out, &s.to_rgba8(), 0, y);
+        y += i64::from(s.height() + GAP);
+    }
+
+    let x = i64::from(left + GAP);
+    image::imageops::replace(&mut out, img, x, 0);
+
+    for r in moved {
+        let (x0, y0) = (x + r.dst.x, r.dst.y);
+        let placed = r.placed();
+        let (x1, y1) = (x0 + i64::from(placed.x) - 1, y0 + i64::from(placed.y) - 1);
+
+        let top_bottom = (x0..=x1).flat_map(|x| [(x, y0), (x, y1)]);
+        let left_right = (y0..=y1).flat_map(|y| [(x0, y), (x1, y)]);
+
+        for (px, py) in top_bottom.chain(left_right) {
+            let (Ok(px), Ok(py)) = (u32::try_from(px), u32::try_from(py)) else {
+                continue;
+            };
+
+            if px < out.width() && py < out.height() {
+                out.put_pixel(px, py, MOVED);
             }
         }
+    }
 
-        Some(ident) => {
-            if let Some(old) = args.insert(ident, arg) {
-                eprintln!("ignored arguments: {old}");
-                None
-            } else {
-                None
+    let mut bytes = Vec::new();
+    out.write_to(
+        &mut std::io::Cursor::new(&mut bytes),
+        image::ImageFormat::Png,
+    )
+    .map_err(Error::Encode)?;
+
+    if let Some(dir) = std::path::Path::new(path).parent() {
+        bbbsc::create_dir_all(dir).await?;
+    }
+    tokio::fs::write(path, bytes).await?;
+
+    Ok(())
+}
+
+/// outputs written so far, by the hash of their content.
+#[derive(Default)]
+struct Dedupe {
+    written: std::sync::Mutex<std::collections::HashMap<u64, Vec<String>>>,
+}
+
+impl Dedupe {
+    /// like `write_new_atomically`, but hardlinks `path` to an earlier output
+    /// when one has the same content, or copies it where that isn't supported.
+    async fn write_new(&self, path: &str, bytes: &[u8]) -> Result<()> {
+        use std::hash::{Hash, Hasher};
+
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        bytes.hash(&mut hasher);
+        let hash = hasher.finish();
+
+        let candidates = self.written.lock().unwrap().get(&hash).cloned();
+
+        // a hash is only a hint
+        for c in candidates.unwrap_or_default() {
+            if tokio::fs::read(&c).await.is_ok_and(|b| b == bytes) {
+                return bbbsc::link_new_atomically(&c, path).await;
             }
         }
-    }) else {
-        eprintln!("unterminated arguments");
-        std::process::exit(1)
-    };
+
+        bbbsc::write_new_atomically(path, bytes).await?;
+
+        let mut written = self.written.lock().unwrap();
+        written.entry(hash).or_default().push(path.to_owned());
+
+        Ok(())
+    }
+}
+
+/// writes the sha-256 of `bytes` written to `path` into `{path}.sha256`, as
+/// `sha256sum` does.
+async fn write_checksum(path: &str, bytes: &[u8]) -> Result<()> {
+    use sha2::Digest;
+
+    let hash = sha2::Sha256::digest(bytes)
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect::<String>();
+
+    // relative, for `sha256sum -c` to be run next to it
+    let name = path.rsplit('/').next().unwrap_or(path);
+
+    let part = format!("{path}.sha256.part");
+    tokio::fs::write(&part, format!("{hash}  {name}\n")).await?;
+    tokio::fs::rename(&part, format!("{path}.sha256")).await?;
+
+    Ok(())
+}
+
+/// the arguments that what's written depends on, one `key=value` a line,
+/// leaving out the books and those only about how it's fetched or reported.
+fn configuration(args: &std::collections::HashMap<String, Vec<String>>) -> String {
+    const IGNORED: &[&str] = &[
+        "target",
+        "dist",
+        "stdin",
+        "state",
+        "force",
+        "yes",
+        "verbose",
+        "log-file",
+        "trace-http",
+        "lenient",
+        "retry-failed-pages",
+        "check-dimensions",
+        "dimension-tolerance",
+        "error-report",
+        "manifest",
+        "manifest-timings",
+        "clean-output",
+        "chapter-concurrency",
+        "resource-concurrency",
+        "max-connections-per-host",
+        "page-timeout",
+        "ramp-up",
+        "delay-ms",
+        "http1-only",
+        "http2-only",
+        "head-first",
+        "basic-auth",
+        "resolve",
+        "cookie-jar",
+        "no-cache",
+        "offline",
+        "tar",
+        "url-cache",
+        "write-queue",
+        "shuffle",
+        "seed",
+        "simulate-errors",
+        "max-bytes",
+        "max-pages",
+        "max-runtime",
+        "min-free-space",
+        "max-decode-memory",
+        "fetch-buffer",
+        "max-total-retries",
+        "compare-baseline",
+        "baseline-tolerance",
+        "debug-pages",
+        "dump-tiles",
+        "debug-visualize",
+        "export-coords",
+    ];
+
+    let mut lines = args
+        .iter()
+        .filter(|(k, _)| !IGNORED.contains(&k.as_str()))
+        .flat_map(|(k, vs)| vs.iter().map(move |v| format!("{k}={v}\n")))
+        .collect::<Vec<_>>();
+    lines.sort();
+
+    lines.concat()
+}
+
+fn parse_args(
+    argv: impl Iterator<Item = String>,
+) -> std::collections::HashMap<String, Vec<String>> {
+    let mut args = std::collections::HashMap::<_, Vec<_>>::new();
+
+    let mut insert = |ident, arg| args.entry(ident).or_default().push(arg);
+
+    let last = argv.fold(None, |state, arg| match (state, arg.strip_prefix("--")) {
+        (None, Some(ident)) => Some(ident.to_owned()),
+        (None, None) => {
+            warning!("unrecognized arguments: {arg}");
+            None
+        }
+
+        // `--ident` directly followed by another one is a flag
+        (Some(flag), Some(ident)) => {
+            insert(flag, "true".to_owned());
+            Some(ident.to_owned())
+        }
+        (Some(ident), None) => {
+            insert(ident, arg);
+            None
+        }
+    });
+
+    if let Some(flag) = last {
+        insert(flag, "true".to_owned());
+    }
 
     args
 }
 
+/// takes the value of `key`, ignoring all but the last one when repeated.
+fn take_last(
+    args: &mut std::collections::HashMap<String, Vec<String>>,
+    key: &str,
+) -> Option<String> {
+    let mut values = args.remove(key)?;
+    let last = values.pop();
+
+    for old in values {
+        warning!("ignored arguments: {old}");
+    }
+
+    last
+}
+
+/// how severe a message is, which colors its prefix.
+enum Level {
+    Error,
+    Warning,
+}
+
+/// prints `msg`, prefixed by `level` if any, to stderr when `stderr`, and logs
+/// it to `--log-file` if given.
+fn emit(level: Option<Level>, msg: std::fmt::Arguments, stderr: bool) {
+    use std::io::IsTerminal;
+
+    static COLOR: std::sync::OnceLock<bool> = std::sync::OnceLock::new();
+    let color = *COLOR.get_or_init(|| {
+        std::io::stderr().is_terminal() && std::env::var_os("NO_COLOR").is_none_or(|v| v.is_empty())
+    });
+
+    if stderr {
+        eprintln!("{}", leveled(level.as_ref(), msg, color));
+    }
+
+    if let Some(log) = LOG_FILE.get() {
+        let line = leveled(level.as_ref(), msg, false);
+        if let Err(e) = log.lock().unwrap().write(&line) {
+            eprintln!(
+                "{}",
+                leveled(
+                    Some(&Level::Warning),
+                    format_args!("couldn't log: {e}"),
+                    color
+                )
+            );
+        }
+    }
+}
+
+/// prefixes `msg` by `level`, in color if `color`.
+fn leveled(level: Option<&Level>, msg: std::fmt::Arguments, color: bool) -> String {
+    let (prefix, ansi) = match level {
+        Some(Level::Error) => ("error", "\x1b[1;31m"),
+        Some(Level::Warning) => ("warning", "\x1b[1;33m"),
+        None => return msg.to_string(),
+    };
+
+    if color {
+        format!("{ansi}{prefix}:\x1b[0m {msg}")
+    } else {
+        format!("{prefix}: {msg}")
+    }
+}
+
+/// where messages are logged with `--log-file`.
+static LOG_FILE: std::sync::OnceLock<std::sync::Mutex<LogFile>> = std::sync::OnceLock::new();
+
+/// a log appended to, which is moved aside to `{path}.1` once it grows beyond
+/// `LogFile::MAX`, replacing the one there.
+struct LogFile {
+    path: String,
+    file: std::fs::File,
+    len: u64,
+}
+
+impl LogFile {
+    const MAX: u64 = 10 << 20;
+
+    fn open(path: &str) -> std::io::Result<Self> {
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        let len = file.metadata()?.len();
+
+        Ok(Self {
+            path: path.to_owned(),
+            file,
+            len,
+        })
+    }
+
+    /// appends `line`, prefixed by the time.
+    fn write(&mut self, line: &str) -> std::io::Result<()> {
+        use std::io::Write;
+
+        if self.len >= Self::MAX {
+            std::fs::rename(&self.path, format!("{}.1", self.path))?;
+            *self = Self::open(&self.path)?;
+        }
+
+        let line = format!("{} {line}\n", timestamp(std::time::SystemTime::now()));
+        self.file.write_all(line.as_bytes())?;
+        self.len += line.len() as u64;
+
+        Ok(())
+    }
+}
+
+#[macro_export]
+macro_rules! error {
+    ($($arg:tt)*) => {
+        emit(Some(Level::Error), format_args!($($arg)*), true)
+    };
+}
+
+#[macro_export]
+macro_rules! warning {
+    ($($arg:tt)*) => {
+        emit(Some(Level::Warning), format_args!($($arg)*), true)
+    };
+}
+
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)*) => {
+        emit(None, format_args!($($arg)*), true)
+    };
+}
+
+/// details, printed with `--verbose` but logged to `--log-file` regardless.
+#[macro_export]
+macro_rules! verbose {
+    ($opts:expr, $($arg:tt)*) => {
+        emit(None, format_args!($($arg)*), $opts.verbose)
+    };
+}
+
 #[macro_export]
 macro_rules! take_arg {
-    ($key:ident from $args:expr) => {
-        let Some($key) = $args.remove(stringify!($key)) else {
-            eprintln!("couldn't recognize {}", stringify!($key));
-            std::process::exit(1)
+    // required ones default when missing, for all of them to be reported at once
+    ($key:ident from $args:ident else $missing:ident) => {
+        let $key = take_last(&mut $args, &stringify!($key).replace('_', "-")).unwrap_or_else(|| {
+            $missing.push(stringify!($key));
+            Default::default()
+        });
+    };
+
+    ($key:ident + from $args:ident else $missing:ident) => {
+        let $key = $args
+            .remove(&stringify!($key).replace('_', "-"))
+            .unwrap_or_else(|| {
+                $missing.push(stringify!($key));
+                Default::default()
+            });
+    };
+
+    // optional ones which may be repeated, each parsed
+    ($key:ident * from $args:ident) => {
+        let $key = $args
+            .remove(&stringify!($key).replace('_', "-"))
+            .unwrap_or_default()
+            .into_iter()
+            .map(|v| match v.parse() {
+                Ok(v) => v,
+                Err(e) => {
+                    error!("couldn't recognize {}: {e}", stringify!($key));
+                    std::process::exit(1)
+                }
+            })
+            .collect::<Vec<_>>();
+    };
+
+    ($key:ident ? from $args:ident) => {
+        let $key = match take_last(&mut $args, &stringify!($key).replace('_', "-")) {
+            None => None,
+            Some(v) => match v.parse() {
+                Ok(v) => Some(v),
+                Err(e) => {
+                    error!("couldn't recognize {}: {e}", stringify!($key));
+                    std::process::exit(1)
+                }
+            },
         };
     };
+
+    ($key:ident from $args:ident or $default:expr) => {
+        take_arg!($key? from $args);
+        let $key = $key.unwrap_or($default);
+    };
 }
 
+/// gets `target` from the cache in files at `path`, otherwise fetches it and
+/// puts it there.
 async fn try_use_cache_otherwise_fetch(
+    opts: &Options,
     path: &str,
     target: &str,
+    accept: Option<&reqwest::header::HeaderValue>,
 ) -> Result<ControlFlow<Error, Vec<u8>>> {
-    match tokio::fs::OpenOptions::new().read(true).open(path).await {
-        Ok(mut f) => {
-            use tokio::io::AsyncReadExt;
+    let cache = &bbbsc::FsCache;
+    let res = try_use_cache_otherwise_fetch_telling(opts, cache, path, target, accept).await?;
+    Ok(res.map_continue(|(bytes, _)| bytes))
+}
 
-            let mut bytes = Vec::new();
-            f.read_to_end(&mut bytes).await?;
+/// same as [`try_use_cache_otherwise_fetch`], in `cache` by the key `path`,
+/// also telling whether the bytes came from it.
+async fn try_use_cache_otherwise_fetch_telling(
+    opts: &Options,
+    cache: &impl bbbsc::CacheBackend,
+    path: &str,
+    target: &str,
+    accept: Option<&reqwest::header::HeaderValue>,
+) -> Result<ControlFlow<Error, (Vec<u8>, bool)>> {
+    let fetched = |res: ControlFlow<Error, Vec<u8>>| res.map_continue(|bytes| (bytes, false));
 
-            Ok(ControlFlow::Continue(bytes))
-        }
-        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
-            use tokio::io::AsyncWriteExt;
+    if let Some(data) = target.strip_prefix("data:") {
+        return decode_data_url(data).map(|b| ControlFlow::Continue((b, false)));
+    }
 
-            let res = match reqwest::get(target).await?.error_for_status() {
-                Ok(r) => r,
-                Err(e) => return Ok(ControlFlow::Break(e.into())),
-            };
+    if opts.no_cache {
+        return fetch(opts, target, accept).await.map(fetched);
+    }
+
+    let (path, _entry) = match &opts.url_cache {
+        Some(c) => (c.path(target), Some(c.lock(target).await)),
+        None => (path.to_owned(), None),
+    };
+
+    match cache.get(&path).await? {
+        Some(bytes) => Ok(ControlFlow::Continue((bytes, true))),
+        None if opts.offline => {
+            // as it's also how each chapter ends
+            if !opts.missed.swap(true, std::sync::atomic::Ordering::Relaxed) {
+                warning!(
+                    "what isn't cached is skipped as offline, ending its chapter, from {target} on"
+                );
+            }
+            verbose!(opts, "{target}: not cached, skipped as offline");
 
-            let bytes = res.bytes().await?.to_vec();
+            Ok(ControlFlow::Break(not_cached(target)))
+        }
+        None => {
+            let _permit = opts.permit(target).await;
+            let res = fetch_resumably(opts, &path, target, accept).await?;
 
-            tokio::fs::OpenOptions::new()
-                .write(true)
-                .create_new(true)
-                .open(path)
-                .await?
-                .write_all(&bytes)
-                .await?;
+            if let ControlFlow::Continue(bytes) = &res {
+                cache.put(&path, bytes).await?;
+                clear_part(&path).await;
+            }
 
-            Ok(ControlFlow::Continue(bytes))
+            Ok(fetched(res))
         }
-        Err(e) => Err(e)?,
     }
 }
 
-#[derive(serde::Deserialize)]
-#[serde(rename_all = "kebab-case")]
-#[allow(unused)]
-struct Ptimg {
-    ptimg_version: usize,
-    resources: std::collections::HashMap<String, Resource>,
-    views: Vec<View>,
+/// what's left of `target` with `--offline`, when it isn't cached.
+fn not_cached(target: &str) -> Error {
+    Error::Validation(format!("{target} isn't cached"))
 }
 
-impl Ptimg {
-    fn restore<'a>(&self, map: impl Fn(&str) -> &'a image::DynamicImage) -> Vec<image::RgbaImage> {
-        self.views
-            .iter()
-            .map(|v| {
-                let mut dst = image::RgbaImage::new(v.width, v.height);
+/// removes what's cached for `target` in `cache`, telling whether there was
+/// anything.
+async fn invalidate_cache(
+    opts: &Options,
+    cache: &impl bbbsc::CacheBackend,
+    path: &str,
+    target: &str,
+) -> Result<bool> {
+    if opts.no_cache || target.starts_with("data:") {
+        return Ok(false);
+    }
 
-                v.coords
-                    .iter()
-                    .map(parse)
-                    .for_each(|(key, rep)| rep.apply(map(key), &mut dst));
+    let (path, _entry) = match &opts.url_cache {
+        Some(c) => (c.path(target), Some(c.lock(target).await)),
+        None => (path.to_owned(), None),
+    };
 
-                dst
-            })
-            .collect::<Vec<_>>()
-    }
+    cache.remove(&path).await
 }
 
-#[derive(serde::Deserialize)]
-#[serde(rename_all = "kebab-case")]
-#[allow(unused)]
-struct Resource {
-    src: String,
-    width: usize,
-    height: usize,
-}
+/// with `--head-first`, asks for the head of `target` before fetching it,
+/// breaking when it isn't there. a server not supporting `HEAD` is let be.
+async fn head_first(
+    opts: &Options,
+    target: &str,
+    accept: Option<&reqwest::header::HeaderValue>,
+) -> Result<ControlFlow<Error>> {
+    use reqwest::StatusCode;
 
-#[derive(serde::Deserialize)]
-#[serde(rename_all = "kebab-case")]
-#[allow(unused)]
-struct View {
-    width: u32,
-    height: u32,
-    coords: Vec<String>,
-}
+    if !opts.head_first {
+        return Ok(ControlFlow::Continue(()));
+    }
 
-struct Vec2<T> {
-    x: T,
-    y: T,
-}
+    let res = opts
+        .send(opts.request(reqwest::Method::HEAD, target, accept))
+        .await?;
 
-impl<T> Vec2<T> {
-    fn new(x: T, y: T) -> Self {
-        Self { x, y }
+    if let StatusCode::METHOD_NOT_ALLOWED | StatusCode::NOT_IMPLEMENTED = res.status() {
+        return Ok(ControlFlow::Continue(()));
     }
-}
 
-struct Replacer {
-    size: Vec2<u32>,
-    src: Vec2<u32>,
-    dst: Vec2<i64>,
-}
+    match res.error_for_status() {
+        Ok(r) => {
+            let size = r.headers().get(reqwest::header::CONTENT_LENGTH);
+            let size = size.and_then(|v| v.to_str().ok()).unwrap_or("unknown");
+            verbose!(opts, "{target} is there, of {size} bytes");
 
-impl Replacer {
-    fn new(size: Vec2<u32>, src: Vec2<u32>, dst: Vec2<i64>) -> Self {
-        Self { size, src, dst }
+            Ok(ControlFlow::Continue(()))
+        }
+        Err(e) => Ok(ControlFlow::Break(e.into())),
     }
 }
 
-impl Replacer {
-    fn apply<T, U>(&self, src: &T, dst: &mut U)
-    where
-        T: image::GenericImageView<Pixel = U::Pixel>,
-        U: image::GenericImage,
-    {
-        use image::imageops::{crop_imm, replace};
+/// fetches `target` into memory only.
+async fn fetch(
+    opts: &Options,
+    target: &str,
+    accept: Option<&reqwest::header::HeaderValue>,
+) -> Result<ControlFlow<Error, Vec<u8>>> {
+    if let Some(data) = target.strip_prefix("data:") {
+        return decode_data_url(data).map(ControlFlow::Continue);
+    }
+
+    if opts.offline {
+        return Ok(ControlFlow::Break(not_cached(target)));
+    }
 
-        let part = crop_imm(src, self.src.x, self.src.y, self.size.x, self.size.y);
-        replace(dst, &*part, self.dst.x, self.dst.y);
+    if let Some(s) = &opts.simulate_errors {
+        s.draw(target)?;
     }
+
+    let _permit = opts.permit(target).await;
+
+    if let ControlFlow::Break(e) = head_first(opts, target, accept).await? {
+        return Ok(ControlFlow::Break(e));
+    }
+
+    let res = match opts
+        .send(opts.get(target, accept))
+        .await?
+        .error_for_status()
+    {
+        Ok(r) => r,
+        Err(e) => return Ok(ControlFlow::Break(e.into())),
+    };
+
+    verbose!(opts, "fetched {} over {:?}", res.url(), res.version());
+
+    let bytes = res.bytes().await?;
+    opts.budget.add_bytes(bytes.len());
+
+    Ok(ControlFlow::Continue(bytes.to_vec()))
 }
 
-fn parse(s: &impl AsRef<str>) -> (&str, Replacer) {
-    use nom::bytes::complete::tag;
-    use nom::character::complete::{alpha1, digit1};
-    use nom::combinator::{all_consuming, map, map_res};
-    use nom::sequence::separated_pair;
-    use nom::IResult;
-    use std::str::FromStr;
+/// fetches `target` for `path` through `{path}.part`, which keeps whatever was
+/// received when interrupted. a later call resumes it with a `Range` request
+/// guarded by `If-Range`, given the server sent a validator for it; otherwise,
+/// or when the server declines, it's fetched from scratch. the result is only
+/// given once its length matches what the server announced, to be put into the
+/// cache, and the part cleared by `clear_part` then.
+async fn fetch_resumably(
+    opts: &Options,
+    path: &str,
+    target: &str,
+    accept: Option<&reqwest::header::HeaderValue>,
+) -> Result<ControlFlow<Error, Vec<u8>>> {
+    use reqwest::header::{CONTENT_RANGE, ETAG, IF_RANGE, LAST_MODIFIED, RANGE};
+    use reqwest::StatusCode;
+    use tokio::io::AsyncWriteExt;
+
+    if let Some(s) = &opts.simulate_errors {
+        s.draw(target)?;
+    }
+
+    opts.ensure_free_space(path)?;
+
+    if let ControlFlow::Break(e) = head_first(opts, target, accept).await? {
+        return Ok(ControlFlow::Break(e));
+    }
+
+    let part = format!("{path}.part");
+    let validator = format!("{path}.part.validator");
+
+    let mut offset = match tokio::fs::metadata(&part).await {
+        Ok(m) => m.len(),
+        Err(_) => 0,
+    };
+
+    let res = loop {
+        let mut req = opts.get(target, accept);
+
+        if offset > 0 {
+            if let Ok(v) = tokio::fs::read_to_string(&validator).await {
+                req = req
+                    .header(RANGE, format!("bytes={offset}-"))
+                    .header(IF_RANGE, v.trim());
+            }
+        }
+
+        let res = opts.send(req).await?;
+
+        // the part is stale, e.g. already complete
+        if res.status() == StatusCode::RANGE_NOT_SATISFIABLE && offset > 0 {
+            offset = 0;
+            let _ = tokio::fs::remove_file(&validator).await;
+            continue;
+        }
+
+        match res.error_for_status() {
+            Ok(r) => break r,
+            Err(e) => return Ok(ControlFlow::Break(e.into())),
+        }
+    };
+
+    verbose!(opts, "fetched {} over {:?}", res.url(), res.version());
+
+    let (mut file, total) = if res.status() == StatusCode::PARTIAL_CONTENT {
+        let total = res
+            .headers()
+            .get(CONTENT_RANGE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.rsplit_once('/'))
+            .and_then(|(_, total)| total.parse::<u64>().ok());
 
-    fn num<T: FromStr>(s: &str) -> IResult<&str, T> {
-        map_res(digit1, |s: &str| s.parse::<T>())(s)
+        let file = tokio::fs::OpenOptions::new()
+            .append(true)
+            .open(&part)
+            .await?;
+
+        (file, total)
+    } else {
+        // weak etags can't be used with `If-Range`
+        let v = match res.headers().get(ETAG) {
+            Some(v) if !v.as_bytes().starts_with(b"W/") => Some(v),
+            _ => res.headers().get(LAST_MODIFIED),
+        };
+
+        match v {
+            Some(v) => tokio::fs::write(&validator, v.as_bytes()).await?,
+            None => {
+                let _ = tokio::fs::remove_file(&validator).await;
+            }
+        }
+
+        (tokio::fs::File::create(&part).await?, res.content_length())
+    };
+
+    let mut res = res;
+    while let Some(chunk) = res.chunk().await? {
+        file.write_all(&chunk).await?;
+        opts.budget.add_bytes(chunk.len());
     }
+    file.flush().await?;
 
-    fn vec<T: FromStr>(s: &str) -> IResult<&str, Vec2<T>> {
-        map(separated_pair(num, tag(","), num), |(l, r)| Vec2::new(l, r))(s)
+    let bytes = tokio::fs::read(&part).await?;
+
+    if let Some(total) = total {
+        if bytes.len() as u64 != total {
+            let _ = tokio::fs::remove_file(&part).await;
+            let _ = tokio::fs::remove_file(&validator).await;
+
+            return Err(Error::Validation(format!(
+                "{target} was {} bytes, expected {total}",
+                bytes.len()
+            )));
+        }
     }
 
-    let src = separated_pair(vec, tag("+"), vec);
-    let bdy = separated_pair(src, tag(">"), vec);
-    let whl = separated_pair(alpha1, tag(":"), bdy);
+    Ok(ControlFlow::Continue(bytes))
+}
+
+/// removes what `fetch_resumably` kept for `path`, once it's cached. the part
+/// may be gone already, e.g. renamed into place by `FsCache`.
+async fn clear_part(path: &str) {
+    let _ = tokio::fs::remove_file(format!("{path}.part")).await;
+    let _ = tokio::fs::remove_file(format!("{path}.part.validator")).await;
+}
+
+/// decodes the part of a `data:` url after the scheme, i.e.
+/// `[<mediatype>][;base64],<data>`. `<data>` is base64 when `;base64` is given,
+/// percent-encoded text otherwise.
+fn decode_data_url(data: &str) -> Result<Vec<u8>> {
+    use base64::Engine;
 
-    match all_consuming(whl)(s.as_ref()) {
-        Ok(("", (key, ((src, size), dst)))) => (key, Replacer::new(size, src, dst)),
+    let Some((meta, payload)) = data.split_once(',') else {
+        return Err(Error::Validation("data url has no `,`".to_owned()));
+    };
 
-        Err(e) => panic!("{e}"),
-        _ => unreachable!(),
+    if meta.ends_with(";base64") {
+        base64::engine::general_purpose::STANDARD
+            .decode(payload)
+            .map_err(|e| Error::Validation(format!("data url has invalid base64: {e}")))
+    } else {
+        Ok(percent_encoding::percent_decode_str(payload).collect())
     }
 }
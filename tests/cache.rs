@@ -0,0 +1,23 @@
+use bbbsc::{CacheBackend, FsCache};
+
+#[tokio::test]
+async fn fs_roundtrip() {
+    let dir = std::env::temp_dir().join(format!("bbbsc-cache-{}", std::process::id()));
+    let key = dir.join("a/b.jpg");
+    let key = key.to_str().unwrap();
+
+    assert_eq!(FsCache.get(key).await.unwrap(), None);
+
+    FsCache.put(key, b"first").await.unwrap();
+    FsCache.put(key, b"second").await.unwrap();
+    assert_eq!(
+        FsCache.get(key).await.unwrap().as_deref(),
+        Some(&b"second"[..])
+    );
+
+    assert!(FsCache.remove(key).await.unwrap());
+    assert!(!FsCache.remove(key).await.unwrap());
+    assert_eq!(FsCache.get(key).await.unwrap(), None);
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
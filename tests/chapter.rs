@@ -0,0 +1,204 @@
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+/// what's served for a request: the body, or `None` for `404`. a request
+/// never answered hangs.
+type Serve = dyn Fn(&str) -> Option<Option<Vec<u8>>> + Send + Sync;
+
+/// serves pages of `fixtures/single.ptimg.json` over http, as `serve` tells,
+/// returning its port.
+async fn server(serve: Arc<Serve>) -> u16 {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+
+    tokio::spawn(async move {
+        loop {
+            let (mut conn, _) = listener.accept().await.unwrap();
+            let serve = serve.clone();
+
+            tokio::spawn(async move {
+                let mut req = Vec::new();
+                let mut buf = [0; 1024];
+                while !req.windows(4).any(|w| w == b"\r\n\r\n") {
+                    match conn.read(&mut buf).await {
+                        Ok(0) | Err(_) => return,
+                        Ok(n) => req.extend(&buf[..n]),
+                    }
+                }
+
+                let req = String::from_utf8_lossy(&req);
+                let path = req.split(' ').nth(1).unwrap_or("/").to_owned();
+
+                let res = match serve(&path) {
+                    None => return std::future::pending().await,
+                    Some(Some(body)) => {
+                        let head = format!(
+                            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                            body.len()
+                        );
+                        [head.into_bytes(), body].concat()
+                    }
+                    Some(None) => {
+                        b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                            .to_vec()
+                    }
+                };
+                let _ = conn.write_all(&res).await;
+            });
+        }
+    });
+
+    port
+}
+
+/// the page as served, of `/book/01/{page:04}.{ext}` up to `pages`.
+fn fixture(path: &str, pages: usize) -> Option<Vec<u8>> {
+    let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+
+    let rest = path.strip_prefix("/book/01/")?;
+    let (page, ext) = rest.split_once('.')?;
+    page.parse::<usize>()
+        .ok()
+        .filter(|p| (1..=pages).contains(p))?;
+
+    match ext {
+        "ptimg.json" => std::fs::read(fixtures.join("single.ptimg.json")).ok(),
+        "jpg" => std::fs::read(fixtures.join("source.png")).ok(),
+        _ => None,
+    }
+}
+
+/// an empty directory of its own for a test.
+fn empty_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("bbbsc-{name}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// runs bbbsc on the book `port` serves in `dir`, whether it succeeded.
+async fn run(dir: &Path, port: u16, args: &[&str]) -> bool {
+    let status = tokio::process::Command::new(env!("CARGO_BIN_EXE_bbbsc"))
+        .current_dir(dir)
+        .arg("--target")
+        .arg(format!("http://127.0.0.1:{port}/{{}}/{{}}/{{}}.{{}}"))
+        .args(["--dist", "book", "--no-cache"])
+        .args(args)
+        .stderr(std::process::Stdio::null())
+        .status()
+        .await
+        .unwrap();
+
+    status.success()
+}
+
+#[tokio::test]
+async fn timed_out_page_is_skipped() {
+    let port = server(Arc::new(|path: &str| match path {
+        "/book/01/0002.jpg" => None,
+        path => Some(fixture(path, 3)),
+    }))
+    .await;
+
+    let dir = empty_dir("timeout");
+    let ok = run(&dir, port, &["--page-timeout", "1"]).await;
+
+    // reported, but not the end of the chapter
+    assert!(!ok);
+    assert!(dir.join("book/01/0001.webp").is_file());
+    assert!(!dir.join("book/01/0002.webp").exists());
+    assert!(!dir.join("book/01/0002.webp.part").exists());
+    assert!(dir.join("book/01/0003.webp").is_file());
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[tokio::test]
+async fn failed_page_is_retried() {
+    let hung = AtomicBool::new(false);
+    let port = server(Arc::new(move |path: &str| match path {
+        // only the first time
+        "/book/01/0002.jpg" if !hung.swap(true, Ordering::SeqCst) => None,
+        path => Some(fixture(path, 3)),
+    }))
+    .await;
+
+    let args = ["--page-timeout", "1", "--retry-failed-pages"];
+    let dir = empty_dir("retry");
+    let ok = run(&dir, port, &args).await;
+
+    assert!(ok);
+    for page in 1..=3 {
+        assert!(dir.join(format!("book/01/{page:04}.webp")).is_file());
+    }
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[tokio::test]
+async fn cbz_is_appended_to() {
+    let pages = Arc::new(AtomicUsize::new(2));
+    let served = pages.clone();
+    let port = server(Arc::new(move |path: &str| {
+        Some(fixture(path, served.load(Ordering::SeqCst)))
+    }))
+    .await;
+
+    let dir = empty_dir("cbz");
+    let args = ["--cbz", "--state"];
+    assert!(run(&dir, port, &args).await);
+    let before = std::fs::read(dir.join("book/01.cbz")).unwrap();
+
+    pages.store(3, Ordering::SeqCst);
+    assert!(run(&dir, port, &args).await);
+    let after = std::fs::read(dir.join("book/01.cbz")).unwrap();
+
+    // the entries there are kept as they were, up to the central directory
+    let at = u32::from_le_bytes(before[before.len() - 6..][..4].try_into().unwrap()) as usize;
+    assert_eq!(before[..at], after[..at]);
+    assert_eq!(
+        u16::from_le_bytes([after[after.len() - 12], after[after.len() - 11]]),
+        3
+    );
+
+    let index = std::fs::read(dir.join("book/01.cbz.index")).unwrap();
+    let index: Vec<serde_json::Value> = serde_json::from_slice(&index).unwrap();
+    let names = index
+        .iter()
+        .map(|e| e["name"].as_str().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(names, ["0001.webp", "0002.webp", "0003.webp"]);
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[tokio::test]
+async fn inline_data_isnt_fetched() {
+    let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let inline = std::fs::read(fixtures.join("inline.ptimg.json")).unwrap();
+
+    // the images aren't there to be fetched
+    let port = server(Arc::new(move |path: &str| match path {
+        "/book/01/0001.ptimg.json" => Some(Some(inline.clone())),
+        _ => Some(None),
+    }))
+    .await;
+
+    let dir = empty_dir("inline");
+    assert!(run(&dir, port, &["--embed-metadata"]).await);
+
+    let page = std::fs::read(dir.join("book/01/0001.webp")).unwrap();
+    let expected = std::fs::read(fixtures.join("multiple.png")).unwrap();
+    assert_eq!(
+        image::load_from_memory(&page).unwrap().to_rgba8(),
+        image::load_from_memory(&expected).unwrap().to_rgba8()
+    );
+
+    // nor is it embedded as where the page came from
+    assert!(!String::from_utf8_lossy(&page).contains("inline"));
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,97 @@
+use bbbsc::parse_coord;
+
+fn parsed(s: &str) -> (String, serde_json::Value) {
+    let (key, rep) = parse_coord(&s).unwrap();
+    (key.to_owned(), serde_json::to_value(rep).unwrap())
+}
+
+#[test]
+fn canonical() {
+    let (key, rep) = parsed("i:1,2+3,4>-5,6");
+
+    assert_eq!(key, "i");
+    assert_eq!(
+        rep,
+        serde_json::json!({
+            "size": { "x": 3, "y": 4 },
+            "src": { "x": 1, "y": 2 },
+            "dst": { "x": -5, "y": 6 },
+        })
+    );
+}
+
+#[test]
+fn whitespace() {
+    assert_eq!(
+        parsed(" i : 1 ,2 + 3, 4 > -5 , 6 "),
+        parsed("i:1,2+3,4>-5,6")
+    );
+    assert_eq!(parsed("i:1,2\t+\n3,4>-5,6"), parsed("i:1,2+3,4>-5,6"));
+}
+
+#[test]
+fn malformed() {
+    assert!(parse_coord(&"i:1,2+3,4").is_err());
+    assert!(parse_coord(&"i:1 2+3,4>5,6").is_err());
+    assert!(parse_coord(&"i:- 1,2+3,4>5,6").is_err());
+}
+
+#[test]
+fn normalized() {
+    use bbbsc::parse_normalized_coord;
+
+    let (key, n) = parse_normalized_coord(&"i:0.25,0+0.5,1>0,0.5").unwrap();
+    let rep = n.to_pixels((8, 8), (8, 4));
+
+    assert_eq!(key, "i");
+    assert_eq!(
+        serde_json::to_value(rep).unwrap(),
+        serde_json::json!({
+            "size": { "x": 4, "y": 8 },
+            "src": { "x": 2, "y": 0 },
+            "dst": { "x": 0, "y": 2 },
+        })
+    );
+
+    assert!(parse_normalized_coord(&"i:-0.5,0+0.5,1>0,0").is_err());
+    assert!(parse_coord(&"i:0.5,0+1,1>0,0").is_err());
+}
+
+#[test]
+fn corners() {
+    assert_eq!(parsed("i:1,2~4,6>-5,6"), parsed("i:1,2+3,4>-5,6"));
+    assert_eq!(parsed("i: 1,2 ~ 4,6 > -5,6"), parsed("i:1,2+3,4>-5,6"));
+    assert_eq!(parsed("i:1,2~1,2>0,0"), parsed("i:1,2+0,0>0,0"));
+
+    assert!(parse_coord(&"i:4,2~1,6>0,0").is_err());
+    assert!(parse_coord(&"i:1,6~4,2>0,0").is_err());
+}
+
+#[test]
+fn scaled() {
+    let (key, rep) = parsed("i:0,0+4,4>1,1 @ 2x");
+
+    assert_eq!(key, "i");
+    assert_eq!(rep["scale"], 2.0);
+    assert_eq!(parsed("i:0,0~4,4>1,1@2x"), (key, rep));
+    assert_eq!(parsed("i:0,0+4,4>1,1@1x"), parsed("i:0,0+4,4>1,1"));
+
+    assert!(parse_coord(&"i:0,0+4,4>1,1@0x").is_err());
+    assert!(parse_coord(&"i:0,0+4,4>1,1@2").is_err());
+}
+
+#[test]
+fn scaled_placement() {
+    let src = image::RgbaImage::from_pixel(4, 4, image::Rgba([255, 0, 0, 255]));
+    let mut dst = image::RgbaImage::new(4, 4);
+
+    let (_, rep) = parse_coord(&"i:0,0+4,4>1,1@2x").unwrap();
+    assert_eq!((rep.placed().x, rep.placed().y), (2, 2));
+
+    rep.apply(&src, &mut dst);
+
+    for (x, y, p) in dst.enumerate_pixels() {
+        let inside = (1..3).contains(&x) && (1..3).contains(&y);
+        assert_eq!(p[3] == 255, inside, "at {x},{y}");
+    }
+}
@@ -0,0 +1,334 @@
+use bbbsc::{Error, Ptimg};
+use image::GenericImageView;
+
+fn fixture(name: &str) -> std::path::PathBuf {
+    std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name)
+}
+
+fn restore(name: &str) -> Vec<image::RgbaImage> {
+    let src = image::open(fixture("source.png")).unwrap();
+
+    let json = std::fs::read(fixture(&format!("{name}.ptimg.json"))).unwrap();
+    let pt = serde_json::from_slice::<Ptimg>(&json).unwrap();
+
+    pt.restore(|_| &src).unwrap()
+}
+
+fn expected(name: &str) -> image::RgbaImage {
+    image::open(fixture(&format!("{name}.png")))
+        .unwrap()
+        .to_rgba8()
+}
+
+#[test]
+fn single_tile() {
+    assert_eq!(restore("single"), [expected("single")]);
+}
+
+#[test]
+fn multiple_tiles() {
+    assert_eq!(restore("multiple"), [expected("multiple")]);
+}
+
+#[test]
+fn negative_destination() {
+    assert_eq!(restore("negative"), [expected("negative")]);
+}
+
+#[test]
+fn undersized_source() {
+    let src = image::open(fixture("source.png"))
+        .unwrap()
+        .crop_imm(0, 0, 4, 4);
+
+    let json = std::fs::read(fixture("single.ptimg.json")).unwrap();
+    let mut pt = serde_json::from_slice::<Ptimg>(&json).unwrap();
+    // without a declared size, the fetched one is what coords address
+    pt.resources.clear();
+
+    assert!(matches!(pt.restore(|_| &src), Err(Error::Validation(_))));
+}
+
+#[test]
+fn reused_buffer_across_views() {
+    let src = image::open(fixture("source.png")).unwrap();
+
+    let view = |name: &str| {
+        let json = std::fs::read(fixture(&format!("{name}.ptimg.json"))).unwrap();
+        let pt = serde_json::from_slice::<serde_json::Value>(&json).unwrap();
+        pt["views"][0].clone()
+    };
+
+    // same sized views in a row reuse the buffer, a differently sized one doesn't
+    let mut pt = serde_json::from_slice::<serde_json::Value>(
+        &std::fs::read(fixture("multiple.ptimg.json")).unwrap(),
+    )
+    .unwrap();
+    pt["views"] = serde_json::json!([
+        view("multiple"),
+        view("single"),
+        view("single"),
+        view("multiple")
+    ]);
+    let pt = serde_json::from_value::<Ptimg>(pt).unwrap();
+
+    let mut each = Vec::new();
+    pt.restore_each_with(
+        |_| &src,
+        &Default::default(),
+        |img| {
+            each.push(img.clone());
+            Ok(())
+        },
+    )
+    .unwrap();
+
+    assert_eq!(each, pt.restore(|_| &src).unwrap());
+}
+
+#[test]
+fn oversized_image() {
+    let png = std::fs::read(fixture("source.png")).unwrap();
+
+    assert!(bbbsc::decode(&png, 64).is_ok());
+    assert!(matches!(bbbsc::decode(&png, 63), Err(Error::Validation(_))));
+}
+
+#[test]
+fn normalized_coords() {
+    let src = image::open(fixture("source.png")).unwrap();
+
+    let json = std::fs::read(fixture("normalized.ptimg.json")).unwrap();
+    let mut pt = serde_json::from_slice::<Ptimg>(&json).unwrap();
+    pt.denormalize(|_| &src).unwrap();
+
+    assert_eq!(pt.views[0].coords[1], "i:4,0+4,4>0,4");
+    assert_eq!(pt.restore(|_| &src).unwrap(), restore("multiple"));
+}
+
+#[test]
+fn unknown_fields() {
+    let src = image::open(fixture("source.png")).unwrap();
+
+    let json = std::fs::read(fixture("multiple.ptimg.json")).unwrap();
+    let mut pt = serde_json::from_slice::<serde_json::Value>(&json).unwrap();
+    pt["ptimg-extension"] = serde_json::json!({ "any": [1, 2] });
+    pt["resources"]["i"]["mime"] = "image/png".into();
+    pt["views"][0]["rotation"] = 0.into();
+
+    let pt = serde_json::from_value::<Ptimg>(pt).unwrap();
+    assert_eq!(pt.restore(|_| &src).unwrap(), [expected("multiple")]);
+
+    // and an undeclared size is the fetched one
+    let mut pt = serde_json::from_slice::<serde_json::Value>(&json).unwrap();
+    pt["resources"]["i"] = serde_json::json!({ "src": "source.png" });
+
+    let pt = serde_json::from_value::<Ptimg>(pt).unwrap();
+    assert_eq!(pt.restore(|_| &src).unwrap(), [expected("multiple")]);
+}
+
+#[test]
+fn problems() {
+    let json = std::fs::read(fixture("multiple.ptimg.json")).unwrap();
+    let pt = serde_json::from_slice::<Ptimg>(&json).unwrap();
+    assert!(pt.problems().is_empty());
+
+    let mut pt = serde_json::from_slice::<serde_json::Value>(&json).unwrap();
+    pt["views"][0]["coords"] = serde_json::json!([
+        "i:0,0+4,4>4,4",
+        "i:0,0+4,4>6,0",
+        "j:0,0+1,1>0,0",
+        "i:6,6+4,4>0,0",
+        "i:0,0"
+    ]);
+    let pt = serde_json::from_value::<Ptimg>(pt).unwrap();
+
+    assert_eq!(pt.problems().len(), 4);
+}
+
+#[test]
+fn two_sources() {
+    let even = image::open(fixture("source.png")).unwrap();
+    let odd = image::open(fixture("multiple.png")).unwrap();
+
+    let json = std::fs::read(fixture("interleaved.ptimg.json")).unwrap();
+    let pt = serde_json::from_slice::<Ptimg>(&json).unwrap();
+
+    let views = pt
+        .restore(|key| match key {
+            "even" => &even,
+            "odd" => &odd,
+            _ => unreachable!("{key}"),
+        })
+        .unwrap();
+
+    // every other pair of rows comes from the other source
+    let (even, odd) = (even.to_rgba8(), odd.to_rgba8());
+    let expected = image::RgbaImage::from_fn(8, 8, |x, y| match y / 2 % 2 {
+        0 => *even.get_pixel(x, y),
+        _ => *odd.get_pixel(x, y),
+    });
+
+    assert_eq!(views, [expected]);
+}
+
+#[test]
+fn view_labels() {
+    let json = std::fs::read(fixture("multiple.ptimg.json")).unwrap();
+    let mut pt = serde_json::from_slice::<serde_json::Value>(&json).unwrap();
+    let mut labelled = pt["views"][0].clone();
+    labelled["label"] = "thumbnail".into();
+    pt["views"].as_array_mut().unwrap().push(labelled);
+
+    let pt = serde_json::from_value::<Ptimg>(pt).unwrap();
+    assert_eq!(pt.views[0].label, None);
+    assert_eq!(pt.views[1].label.as_deref(), Some("thumbnail"));
+}
+
+#[test]
+fn view_names() {
+    let view = |label: Option<&str>| {
+        serde_json::from_value::<bbbsc::View>(serde_json::json!({
+            "width": 1, "height": 1, "coords": [], "label": label,
+        }))
+        .unwrap()
+    };
+
+    let views = [
+        None,
+        Some("a"),
+        Some("a"),
+        Some("a-3"),
+        None,
+        Some("5"),
+        Some("a b"),
+    ]
+    .map(view);
+    let names = bbbsc::view_names(&views);
+
+    // the repeat takes `a-3`, so the label of that is suffixed in turn, as is
+    // the one clashing with the position of the unlabelled view before it
+    assert_eq!(names, ["a", "a-3", "a-3-4", "5", "5-6", "a_b"]);
+}
+
+#[test]
+fn size_mismatch() {
+    let src = image::open(fixture("source.png")).unwrap();
+
+    let json = std::fs::read(fixture("multiple.ptimg.json")).unwrap();
+    let mut pt = serde_json::from_slice::<Ptimg>(&json).unwrap();
+    assert!(pt.resources["i"].matches(&src));
+
+    // e.g. a low resolution placeholder cached earlier
+    let placeholder = src.thumbnail_exact(4, 4);
+    assert!(!pt.resources["i"].matches(&placeholder));
+
+    // an undeclared size matches anything
+    let i = pt.resources.get_mut("i").unwrap();
+    (i.width, i.height) = (0, 0);
+    assert!(pt.resources["i"].matches(&placeholder));
+}
+
+#[test]
+fn icc_profile() {
+    let png = std::fs::read(fixture("source.png")).unwrap();
+    let (_, icc) = bbbsc::decode_with_profile(&png, 64).unwrap();
+    assert_eq!(icc, None);
+
+    // tagged with a linear profile
+    let webp = std::fs::read(fixture("linear.webp")).unwrap();
+    let (img, icc) = bbbsc::decode_with_profile(&webp, 64).unwrap();
+    assert_eq!(img.dimensions(), (8, 8));
+    assert_eq!(&icc.unwrap()[36..40], b"acsp");
+}
+
+#[test]
+fn z_order() {
+    let src = image::open(fixture("source.png")).unwrap();
+
+    let json = std::fs::read(fixture("single.ptimg.json")).unwrap();
+    let mut pt = serde_json::from_slice::<serde_json::Value>(&json).unwrap();
+    // overlapping at 2,2 to 4,4
+    pt["views"][0]["coords"] = serde_json::json!(["i:0,0+4,4>0,0", "i:4,4+4,4>2,2"]);
+
+    let restore = |pt: &serde_json::Value| {
+        let pt = serde_json::from_value::<Ptimg>(pt.clone()).unwrap();
+        pt.restore(|_| &src).unwrap().remove(0)
+    };
+    let (first, second) = (src.get_pixel(3, 3), src.get_pixel(5, 5));
+    assert_ne!(first, second);
+
+    // array order, the later one on top
+    assert_eq!(*restore(&pt).get_pixel(3, 3), second);
+
+    pt["views"][0]["z-index"] = serde_json::json!([1, 0]);
+    assert_eq!(*restore(&pt).get_pixel(3, 3), first);
+
+    // equal ones keep array order
+    pt["views"][0]["z-index"] = serde_json::json!([1]);
+    assert_eq!(*restore(&pt).get_pixel(3, 3), first);
+    pt["views"][0]["z-index"] = serde_json::json!([0, 0]);
+    assert_eq!(*restore(&pt).get_pixel(3, 3), second);
+}
+
+#[test]
+fn inline_data() {
+    let json = std::fs::read(fixture("inline.ptimg.json")).unwrap();
+    let pt = serde_json::from_slice::<Ptimg>(&json).unwrap();
+
+    let bytes = pt.resources["i"].inline().unwrap().unwrap();
+    let src = bbbsc::decode(&bytes, 64).unwrap();
+
+    assert_eq!(pt.restore(|_| &src).unwrap(), [expected("multiple")]);
+    assert!(pt.problems().is_empty());
+}
+
+#[test]
+fn wrapped_across_right_edge() {
+    // a 4x1 view with a 2x1 tile placed at its last column
+    let src = image::RgbaImage::from_fn(2, 1, |x, _| image::Rgba([x as u8 + 1, 0, 0, 255]));
+    let src = image::DynamicImage::ImageRgba8(src);
+    let pt = br#"{
+        "ptimg-version": 1,
+        "resources": { "i": { "src": "0001.jpg", "width": 2, "height": 1 } },
+        "views": [{ "width": 4, "height": 1, "coords": ["i:0,0+2,1>3,0"] }]
+    }"#;
+    let pt = Ptimg::from_slice(pt).unwrap();
+
+    let clipped = pt.restore(|_| &src).unwrap();
+    assert_eq!(clipped[0].get_pixel(3, 0)[0], 1);
+    assert_eq!(clipped[0].get_pixel(0, 0)[3], 0);
+
+    let opts = bbbsc::RestoreOptions {
+        wrap: true,
+        ..Default::default()
+    };
+    let wrapped = pt.restore_with(|_| &src, &opts).unwrap();
+    assert_eq!(wrapped[0].get_pixel(3, 0)[0], 1);
+    assert_eq!(wrapped[0].get_pixel(0, 0)[0], 2);
+    assert_eq!(wrapped[0].get_pixel(1, 0)[3], 0);
+}
+
+#[test]
+fn dispatched_by_version() {
+    let src = image::open(fixture("source.png")).unwrap();
+    let json = std::fs::read(fixture("single.ptimg.json")).unwrap();
+
+    let mut pt = serde_json::from_slice::<Ptimg>(&json).unwrap();
+    assert_eq!(pt.restore(|_| &src).unwrap(), [expected("single")]);
+
+    pt.ptimg_version = 2;
+    let Err(Error::Validation(e)) = pt.restore(|_| &src) else {
+        panic!("version 2 restored");
+    };
+    assert!(e.contains("isn't supported yet"));
+
+    pt.ptimg_version = 3;
+    let Err(Error::Validation(e)) = pt.restore(|_| &src) else {
+        panic!("version 3 restored");
+    };
+    assert!(e.contains("unknown ptimg-version 3"));
+    assert_eq!(pt.problems(), ["unknown ptimg-version 3"]);
+}
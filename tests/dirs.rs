@@ -0,0 +1,25 @@
+#[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+async fn concurrent_create_dir_all() {
+    let root = std::env::temp_dir().join(format!("bbbsc-dirs-{}", std::process::id()));
+
+    for round in 0..16 {
+        let path = root.join(format!("{round}/a/b/c/d"));
+
+        let tasks = (0..64)
+            .map(|_| tokio::spawn(bbbsc::create_dir_all(path.clone())))
+            .collect::<Vec<_>>();
+
+        for t in tasks {
+            t.await.unwrap().unwrap();
+        }
+        assert!(path.is_dir());
+    }
+
+    // a file in the way is still an error
+    let file = root.join("file");
+    std::fs::write(&file, b"").unwrap();
+    assert!(bbbsc::create_dir_all(&file).await.is_err());
+    assert!(bbbsc::create_dir_all(file.join("dir")).await.is_err());
+
+    std::fs::remove_dir_all(root).unwrap();
+}
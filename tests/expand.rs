@@ -0,0 +1,41 @@
+use bbbsc::expand_env;
+
+fn env(name: &str) -> Option<String> {
+    match name {
+        "BASE_URL" => Some("https://example.com".to_owned()),
+        "EMPTY" => Some(String::new()),
+        _ => None,
+    }
+}
+
+#[test]
+fn variables() {
+    assert_eq!(
+        expand_env("${BASE_URL}/{}/{}/{}.{}", env).unwrap(),
+        "https://example.com/{}/{}/{}.{}"
+    );
+    assert_eq!(
+        expand_env("${BASE_URL:-x}", env).unwrap(),
+        "https://example.com"
+    );
+}
+
+#[test]
+fn defaults() {
+    assert_eq!(expand_env("${DIST:-book}", env).unwrap(), "book");
+    assert_eq!(expand_env("${EMPTY:-book}", env).unwrap(), "book");
+    assert_eq!(expand_env("${DIST:-}", env).unwrap(), "");
+    assert_eq!(expand_env("${EMPTY}", env).unwrap(), "");
+}
+
+#[test]
+fn literal_dollars() {
+    assert_eq!(expand_env("$${BASE_URL}", env).unwrap(), "${BASE_URL}");
+    assert_eq!(expand_env("a$b$", env).unwrap(), "a$b$");
+}
+
+#[test]
+fn undefined() {
+    assert!(expand_env("${DIST}", env).is_err());
+    assert!(expand_env("${BASE_URL", env).is_err());
+}
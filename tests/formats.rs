@@ -0,0 +1,9 @@
+#[test]
+fn sources() {
+    let formats = bbbsc::formats();
+
+    for name in ["jpg", "png", "webp"] {
+        let f = formats.iter().find(|f| f.name == name).unwrap();
+        assert!(f.decode, "{name} isn't decoded");
+    }
+}
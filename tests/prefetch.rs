@@ -0,0 +1,45 @@
+use std::num::NonZeroUsize;
+use std::ops::ControlFlow;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+#[tokio::test]
+async fn bounded_under_slow_consumer() {
+    let buffer = NonZeroUsize::new(3).unwrap();
+    let (waiting, most) = (Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0)));
+
+    let (w, m) = (waiting.clone(), most.clone());
+    let mut items = bbbsc::prefetch(0..50, buffer, move |n| {
+        let (w, m) = (w.clone(), m.clone());
+        async move {
+            m.fetch_max(w.fetch_add(1, Ordering::SeqCst) + 1, Ordering::SeqCst);
+            ControlFlow::Continue(n)
+        }
+    });
+
+    for n in 0..50 {
+        tokio::time::sleep(std::time::Duration::from_millis(2)).await;
+        assert_eq!(items.next().await, Some(n));
+        waiting.fetch_sub(1, Ordering::SeqCst);
+    }
+    assert_eq!(items.next().await, None);
+
+    // those waiting, and the one fetched but not yet handed over
+    assert!(most.load(Ordering::SeqCst) <= buffer.get() + 1);
+}
+
+#[tokio::test]
+async fn stops_after_break() {
+    let one = NonZeroUsize::new(1).unwrap();
+    let mut items = bbbsc::prefetch(0.., one, |n| async move {
+        match n {
+            2 => ControlFlow::Break(n),
+            n => ControlFlow::Continue(n),
+        }
+    });
+
+    for n in 0..=2 {
+        assert_eq!(items.next().await, Some(n));
+    }
+    assert_eq!(items.next().await, None);
+}
@@ -0,0 +1,35 @@
+#[tokio::test]
+async fn write_outlasts_timeout() {
+    let root = std::env::temp_dir().join(format!("bbbsc-publish-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&root);
+    std::fs::create_dir_all(&root).unwrap();
+
+    let path = root.join("0001.webp");
+    let part = root.join("0001.webp.part");
+    let path = path.to_str().unwrap();
+    let bytes = vec![7; 32 << 20];
+
+    // timed out while still writing
+    let write = bbbsc::write_new_atomically(path, &bytes);
+    let res = tokio::time::timeout(std::time::Duration::ZERO, write).await;
+    assert!(res.is_err());
+
+    for _ in 0..100 {
+        if std::path::Path::new(path).exists() && !part.exists() {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    }
+
+    // written in full all the same
+    assert!(!part.exists());
+    assert!(std::fs::read(path).unwrap() == bytes);
+
+    // and refused once it's there, leaving it as it was
+    assert!(bbbsc::write_new_atomically(path, b"").await.is_err());
+    assert!(bbbsc::link_new_atomically(path, path).await.is_err());
+    assert!(!part.exists());
+    assert_eq!(std::fs::metadata(path).unwrap().len(), bytes.len() as u64);
+
+    std::fs::remove_dir_all(root).unwrap();
+}
@@ -0,0 +1,34 @@
+use bbbsc::substitute;
+
+#[test]
+fn path() {
+    let url = substitute("https://example.com/{}/{}.{}", "a b&c");
+    assert_eq!(url, "https://example.com/a b&c/{}.{}");
+}
+
+#[test]
+fn query() {
+    let url = "https://example.com/view?book={}&ch={}&pg={}";
+    let url = substitute(url, "a b&c=d");
+    let url = substitute(&url, "01");
+    let url = substitute(&url, "0003");
+
+    assert_eq!(
+        url,
+        "https://example.com/view?book=a%20b%26c%3Dd&ch=01&pg=0003"
+    );
+}
+
+#[test]
+fn query_after_path() {
+    let url = substitute("https://example.com/{}/page?kind={}", "book");
+    let url = substitute(&url, "ptimg.json");
+
+    assert_eq!(url, "https://example.com/book/page?kind=ptimg.json");
+}
+
+#[test]
+fn data_url() {
+    let url = "data:application/json,{}";
+    assert_eq!(substitute(url, "01"), url);
+}